@@ -1,3 +0,0 @@
-#!/bin/bash
-set -euo pipefail
-exec /opt/gallium/bin/pubkey show
@@ -0,0 +1,11 @@
+create table personal_access_tokens (
+    id          serial      primary key
+  , created_at  timestamp   not null default CURRENT_TIMESTAMP
+  , user_id     integer     not null
+  , name        text        not null
+  , token_hash  varchar(64) not null unique
+  , scopes      text[]      not null
+  , expires_at  timestamp
+  , revoked     boolean     not null default false
+  , foreign key (user_id) references users(id) on delete cascade
+);
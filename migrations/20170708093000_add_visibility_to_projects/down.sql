@@ -0,0 +1,2 @@
+alter table projects drop constraint CK_projects_visibility;
+alter table projects drop column visibility;
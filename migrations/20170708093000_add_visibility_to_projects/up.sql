@@ -0,0 +1,2 @@
+alter table projects add column visibility text not null default 'public';
+alter table projects add constraint CK_projects_visibility check (visibility in ('public', 'internal', 'private'));
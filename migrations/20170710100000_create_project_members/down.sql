@@ -0,0 +1 @@
+drop table project_members;
@@ -0,0 +1,11 @@
+create table project_members (
+    id          serial    primary key
+  , created_at  timestamp not null default CURRENT_TIMESTAMP
+  , project_id  integer   not null
+  , user_id     integer   not null
+  , role        text      not null
+  , foreign key (project_id) references projects(id) on delete cascade
+  , foreign key (user_id) references users(id) on delete cascade
+  , constraint UC_project_members unique (project_id, user_id)
+  , constraint CK_project_members_role check (role in ('guest', 'reporter', 'developer', 'maintainer', 'owner'))
+);
@@ -0,0 +1,11 @@
+drop index UI_projects_organization;
+drop index UI_projects_user;
+alter table projects drop column organization_id;
+alter table projects add constraint UC_projects unique (user_id, name);
+
+drop trigger TR_organizations_namespace on organizations;
+drop trigger TR_users_namespace on users;
+drop function check_namespace_collision();
+
+drop table organization_members;
+drop table organizations;
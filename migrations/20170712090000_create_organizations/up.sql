@@ -0,0 +1,41 @@
+create table organizations (
+    id            serial    primary key
+  , created_at    timestamp not null default CURRENT_TIMESTAMP
+  , name          text      not null unique
+  , display_name  text
+  , description   text
+);
+
+create table organization_members (
+    id              serial    primary key
+  , created_at      timestamp not null default CURRENT_TIMESTAMP
+  , organization_id integer   not null
+  , user_id         integer   not null
+  , role            text      not null
+  , foreign key (organization_id) references organizations(id) on delete cascade
+  , foreign key (user_id) references users(id) on delete cascade
+  , constraint UC_organization_members unique (organization_id, user_id)
+  , constraint CK_organization_members_role check (role in ('guest', 'reporter', 'developer', 'maintainer', 'owner'))
+);
+
+-- Users and organizations share the same namespace, since both appear as the first component of repository paths.
+create function check_namespace_collision() returns trigger as $$
+begin
+  if (TG_TABLE_NAME = 'users' and exists (select 1 from organizations where name = NEW.name))
+     or (TG_TABLE_NAME = 'organizations' and exists (select 1 from users where name = NEW.name)) then
+    raise exception 'The namespace "%" is already taken', NEW.name using errcode = 'unique_violation';
+  end if;
+  return NEW;
+end;
+$$ language plpgsql;
+
+create trigger TR_users_namespace before insert or update of name on users
+  for each row execute procedure check_namespace_collision();
+create trigger TR_organizations_namespace before insert or update of name on organizations
+  for each row execute procedure check_namespace_collision();
+
+-- Projects owned by an organization keep the creator in `user_id`.
+alter table projects add column organization_id integer references organizations(id);
+alter table projects drop constraint UC_projects;
+create unique index UI_projects_user on projects (user_id, name) where organization_id is null;
+create unique index UI_projects_organization on projects (organization_id, name) where organization_id is not null;
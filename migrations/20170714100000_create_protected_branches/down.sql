@@ -0,0 +1 @@
+drop table protected_branches;
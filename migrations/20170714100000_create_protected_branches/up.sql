@@ -0,0 +1,12 @@
+create table protected_branches (
+    id                serial    primary key
+  , created_at        timestamp not null default CURRENT_TIMESTAMP
+  , project_id        integer   not null
+  , pattern           text      not null
+  , push_role         text      not null default 'maintainer'
+  , allow_force_push  boolean   not null default false
+  , allow_deletion    boolean   not null default false
+  , foreign key (project_id) references projects(id) on delete cascade
+  , constraint UC_protected_branches unique (project_id, pattern)
+  , constraint CK_protected_branches_push_role check (push_role in ('guest', 'reporter', 'developer', 'maintainer', 'owner'))
+);
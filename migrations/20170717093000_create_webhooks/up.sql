@@ -0,0 +1,31 @@
+create table webhooks (
+    id          serial    primary key
+  , created_at  timestamp not null default CURRENT_TIMESTAMP
+  , project_id  integer   not null
+  , url         text      not null
+  , secret      text
+  , events      text[]    not null
+  , active      boolean   not null default true
+  , foreign key (project_id) references projects(id) on delete cascade
+);
+
+-- Deliveries keep a copy of the destination and the signature, so that they can still be sent
+-- (and logged) after the webhook or the project has been removed.
+create table webhook_deliveries (
+    id              serial    primary key
+  , created_at      timestamp not null default CURRENT_TIMESTAMP
+  , webhook_id      integer
+  , event           text      not null
+  , url             text      not null
+  , payload         text      not null
+  , signature       text
+  , attempts        integer   not null default 0
+  , next_attempt_at timestamp not null default CURRENT_TIMESTAMP
+  , delivered_at    timestamp
+  , status_code     integer
+  , response_body   text
+  , error           text
+  , foreign key (webhook_id) references webhooks(id) on delete set null
+);
+
+create index IX_webhook_deliveries_pending on webhook_deliveries (next_attempt_at) where delivered_at is null;
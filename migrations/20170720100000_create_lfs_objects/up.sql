@@ -0,0 +1,11 @@
+-- The content of objects is stored on the filesystem (see `lfs::object_path`), and the table records
+-- which project has uploaded it.
+create table lfs_objects (
+    id          serial    primary key
+  , created_at  timestamp not null default CURRENT_TIMESTAMP
+  , project_id  integer   not null
+  , oid         text      not null
+  , size        bigint    not null
+  , foreign key (project_id) references projects(id) on delete cascade
+  , constraint UC_lfs_objects unique (project_id, oid)
+);
@@ -0,0 +1 @@
+drop table lfs_locks;
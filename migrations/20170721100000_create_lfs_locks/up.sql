@@ -0,0 +1,10 @@
+create table lfs_locks (
+    id          serial    primary key
+  , created_at  timestamp not null default CURRENT_TIMESTAMP
+  , project_id  integer   not null
+  , user_id     integer   not null
+  , path        text      not null
+  , foreign key (project_id) references projects(id) on delete cascade
+  , foreign key (user_id) references users(id) on delete cascade
+  , constraint UC_lfs_locks unique (project_id, path)
+);
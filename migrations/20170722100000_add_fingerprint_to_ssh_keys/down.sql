@@ -0,0 +1,2 @@
+drop index IX_ssh_keys_fingerprint;
+alter table ssh_keys drop column fingerprint;
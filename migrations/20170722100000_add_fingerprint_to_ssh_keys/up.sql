@@ -0,0 +1,7 @@
+-- The SHA256 fingerprint of the key in the form of `ssh-keygen -l` (`SHA256:<base64 without padding>`),
+-- which is used to look up the key in `pubkey lookup`.
+--
+-- The fingerprints of the existing keys are filled in by the server on startup.
+alter table ssh_keys add column fingerprint text;
+
+create index IX_ssh_keys_fingerprint on ssh_keys (fingerprint);
@@ -0,0 +1,3 @@
+drop index UI_ssh_keys_fingerprint;
+create index IX_ssh_keys_fingerprint on ssh_keys (fingerprint);
+alter table ssh_keys drop column fingerprint_md5;
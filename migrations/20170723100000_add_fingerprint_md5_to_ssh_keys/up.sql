@@ -0,0 +1,25 @@
+-- The MD5 fingerprint of the key in the form of `ssh-keygen -l -E md5` (`MD5:<colon-separated hex>`),
+-- which is filled in by the server on startup for the existing keys.
+alter table ssh_keys add column fingerprint_md5 text;
+
+-- A key must belong to only one user. The duplicated keys are not removed here since it is not
+-- obvious which user should keep them, so the migration fails until they are resolved by hand.
+-- The fingerprints of the existing keys may not be computed yet, so they are compared by the body.
+do $$
+declare
+  conflicts text;
+begin
+  select string_agg(body || ' (ids: ' || ids || ')', ', ')
+    into conflicts
+    from (select split_part(key, ' ', 2) as body, string_agg(id::text, ', ' order by id) as ids
+            from ssh_keys
+           group by split_part(key, ' ', 2)
+          having count(*) > 1) d;
+  if conflicts is not null then
+    raise exception 'ssh_keys contains keys registered more than once: %', conflicts;
+  end if;
+end
+$$;
+
+drop index IX_ssh_keys_fingerprint;
+create unique index UI_ssh_keys_fingerprint on ssh_keys (fingerprint);
@@ -52,10 +52,12 @@ pub fn derive_iron_route(input: TokenStream) -> TokenStream {
             let path = path.expect("failed to parse attribute");
             let handler = handler.expect("failed to parse attribute");
 
-            // extract route parameters
+            // extract route parameters, including a glob parameter (e.g. `*path`) which matches the rest of path
             let params: Vec<&str> = path.split("/")
                 .filter_map(|s| if s.starts_with(":") {
                     Some(s.trim_left_matches(":"))
+                } else if s.starts_with("*") {
+                    Some(s.trim_left_matches("*"))
                 } else {
                     None
                 })
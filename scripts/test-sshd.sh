@@ -0,0 +1,59 @@
+#!/bin/bash
+# Clones from and pushes to a project through the built-in SSH server.
+#
+# Both `server` and `sshd` must be running against the same database, e.g.:
+#   $ server &
+#   $ sshd --listen 127.0.0.1:2222 --host-key ssh_host_ed25519_key &
+#   $ scripts/test-sshd.sh
+set -euo pipefail
+
+api_url="${GALLIUM_URL:-http://localhost:3000}/api/v1"
+ssh_host="${SSHD_HOST:-127.0.0.1}"
+ssh_port="${SSHD_PORT:-2222}"
+
+workdir="$(mktemp -d)"
+trap 'rm -rf "$workdir"' EXIT
+
+name="sshd-test-$(date +%s)-$$"
+password="$(head -c 16 /dev/urandom | base64)"
+
+api() {
+    local method="$1" path="$2"
+    shift 2
+    curl -sSf -X "$method" -H "Content-Type: application/json" "$@" "$api_url$path"
+}
+
+user_id=$(api POST /users -d "{\"name\":\"$name\",\"password\":\"$password\"}" | jq -r .id)
+token=$(api POST /login -d "{\"username\":\"$name\",\"password\":\"$password\"}" | jq -r .access_token)
+
+ssh-keygen -q -t ed25519 -N '' -f "$workdir/id_ed25519"
+ssh-keygen -q -t ed25519 -N '' -f "$workdir/id_unknown"
+api POST /ssh_keys -H "Authorization: Bearer $token" \
+    -d "$(jq -n --arg key "$(cat "$workdir/id_ed25519.pub")" --argjson user_id "$user_id" \
+        '{key: $key, user_id: $user_id}')" > /dev/null
+api POST /projects -H "Authorization: Bearer $token" \
+    -d "{\"user\":\"$name\",\"name\":\"repo\"}" > /dev/null
+
+ssh_opts="-p $ssh_port -o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null -o IdentitiesOnly=yes"
+url="git@$ssh_host:$name/repo.git"
+
+# The unregistered key must be rejected.
+if GIT_SSH_COMMAND="ssh $ssh_opts -i $workdir/id_unknown" git ls-remote "$url" 2> /dev/null; then
+    echo "an unregistered key was accepted" >&2
+    exit 1
+fi
+
+export GIT_SSH_COMMAND="ssh $ssh_opts -i $workdir/id_ed25519"
+export GIT_AUTHOR_NAME="$name" GIT_AUTHOR_EMAIL="$name@example.com"
+export GIT_COMMITTER_NAME="$name" GIT_COMMITTER_EMAIL="$name@example.com"
+
+git clone -q "$url" "$workdir/push"
+# A large incompressible file makes a pack much bigger than the buffers of the server.
+head -c 32M /dev/urandom > "$workdir/push/blob"
+git -C "$workdir/push" add blob
+git -C "$workdir/push" commit -q -m "Add a large blob"
+git -C "$workdir/push" push -q origin HEAD:master
+
+git clone -q "$url" "$workdir/clone"
+cmp "$workdir/push/blob" "$workdir/clone/blob"
+echo "ok"
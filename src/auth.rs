@@ -0,0 +1,96 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use iron::{Request, IronResult, IronError, BeforeMiddleware};
+use iron::headers::{Authorization, Bearer};
+use iron::modifiers::Header;
+use iron::status;
+use jsonwebtoken::{self, Validation};
+
+use config::Config;
+use db::DB;
+use error::{AppResult, AppError};
+use models::{User, PersonalAccessToken};
+use models::personal_access_tokens::SCOPE_API;
+use routes::WWWAuthenticate;
+
+
+/// The lifetime of issued access tokens, in seconds.
+pub const TOKEN_LIFETIME: u64 = 60 * 60 * 24;
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: i32,
+    pub name: String,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+pub fn issue_token(config: &Config, user: &User) -> AppResult<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| AppError::from("system clock is before the UNIX epoch"))?
+        .as_secs();
+    let claims = Claims {
+        sub: user.id,
+        name: user.name.clone(),
+        iat: now,
+        exp: now + TOKEN_LIFETIME,
+    };
+    jsonwebtoken::encode(&Default::default(), &claims, config.jwt_secret.as_bytes()).map_err(Into::into)
+}
+
+pub fn verify_token(config: &Config, token: &str) -> AppResult<Claims> {
+    let data = jsonwebtoken::decode::<Claims>(token, config.jwt_secret.as_bytes(), &Validation::default())?;
+    Ok(data.claims)
+}
+
+
+fn invalid_token(err: AppError) -> IronError {
+    IronError::new(err, (
+        status::Unauthorized,
+        Header(WWWAuthenticate(
+            "Bearer realm=\"main\", error=\"invalid_token\"".to_owned(),
+        )),
+    ))
+}
+
+
+/// Validates `Authorization: Bearer` tokens (either JWTs issued by `/login` or
+/// personal access tokens with `api` scope) and stores the authenticated `User`
+/// into the request extensions.
+///
+/// Requests without a bearer token are passed through as anonymous.
+pub struct AuthMiddleware;
+
+impl BeforeMiddleware for AuthMiddleware {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let token = match req.headers.get::<Authorization<Bearer>>() {
+            Some(&Authorization(Bearer { ref token })) => token.clone(),
+            None => return Ok(()),
+        };
+
+        let conn = DB::from_req(req).map_err(
+            |err| IronError::new(err, status::InternalServerError),
+        )?;
+
+        // JWTs always contain dots, whereas personal access tokens are plain hex strings.
+        let user = if token.contains('.') {
+            let config = req.extensions.get::<Config>().unwrap().clone();
+            let claims = verify_token(&config, &token).map_err(invalid_token)?;
+            User::find_by_id(&conn, claims.sub)
+                .map_err(|err| IronError::new(err, status::InternalServerError))?
+                .ok_or_else(|| invalid_token(AppError::from("The user does not exist")))?
+        } else {
+            let (user, token) = PersonalAccessToken::authenticate(&conn, &token)
+                .map_err(|err| IronError::new(err, status::InternalServerError))?
+                .ok_or_else(|| invalid_token(AppError::from("The token is invalid")))?;
+            if !token.has_scope(SCOPE_API) {
+                return Err(invalid_token(AppError::from("The token does not have `api` scope")));
+            }
+            user
+        };
+
+        req.extensions.insert::<User>(user);
+        Ok(())
+    }
+}
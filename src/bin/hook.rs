@@ -0,0 +1,100 @@
+extern crate gallium;
+extern crate clap;
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use gallium::config::Config;
+use gallium::db::DB;
+use gallium::hooks;
+use gallium::models::{Project, Repository};
+
+
+fn build_cli<'a, 'b: 'a>() -> clap::App<'a, 'b> {
+    clap::App::new("hook")
+        .about("server-side git hooks")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .setting(clap::AppSettings::VersionlessSubcommands)
+        .subcommand(
+            clap::SubCommand::with_name("update")
+                .about("Checks an update of a reference")
+                .arg_from_usage("<refname>  'The name of the reference being updated'")
+                .arg_from_usage("<old>      'The old object name'")
+                .arg_from_usage("<new>      'The new object name'"),
+        )
+        .subcommand(clap::SubCommand::with_name("post-receive").about(
+            "Notifies the updated references",
+        ))
+}
+
+fn main() {
+    let ref matches = build_cli().get_matches();
+    let err = match matches.subcommand() {
+        ("update", Some(m)) => update(m),
+        ("post-receive", Some(m)) => post_receive(m),
+        _ => unreachable!(),
+    };
+    if let Err(err) = err {
+        let _ = writeln!(&mut std::io::stderr(), "{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn update(m: &clap::ArgMatches) -> Result<(), String> {
+    // Pushes which are not made through gallium (e.g. by administrators on the server) are not checked.
+    let project_id: i32 = match env::var(hooks::ENV_PROJECT_ID).ok().and_then(|s| s.parse().ok()) {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let user_id = env::var(hooks::ENV_USER_ID).ok().and_then(|s| s.parse().ok());
+
+    let config = Config::load().map_err(|err| err.to_string())?;
+    let db = DB::new(&config.database_url).map_err(|err| err.to_string())?;
+    let conn = db.get_db_conn().map_err(|err| err.to_string())?;
+
+    let project = Project::find_by_id(&conn, project_id)
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| "The project is not created".to_owned())?;
+
+    let refname = m.value_of("refname").unwrap();
+    let old = m.value_of("old").unwrap();
+    let new = m.value_of("new").unwrap();
+    match hooks::check_update(&*conn, &project, user_id, refname, old, new) {
+        Ok(None) => Ok(()),
+        Ok(Some(message)) => Err(message),
+        Err(err) => Err(format!("Failed to check the update of {}: {}", refname, err)),
+    }
+}
+
+fn post_receive(_m: &clap::ArgMatches) -> Result<(), String> {
+    let project_id: i32 = match env::var(hooks::ENV_PROJECT_ID).ok().and_then(|s| s.parse().ok()) {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+    let user_id = env::var(hooks::ENV_USER_ID).ok().and_then(|s| s.parse().ok());
+
+    // Each line of the standard input is in the form of `<old> <new> <refname>`.
+    let stdin = io::stdin();
+    let mut updates = Vec::new();
+    for line in stdin.lock().lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        let elems: Vec<_> = line.split_whitespace().collect();
+        if elems.len() == 3 {
+            updates.push((elems[2].to_owned(), elems[0].to_owned(), elems[1].to_owned()));
+        }
+    }
+
+    let config = Config::load().map_err(|err| err.to_string())?;
+    let db = DB::new(&config.database_url).map_err(|err| err.to_string())?;
+    let conn = db.get_db_conn().map_err(|err| err.to_string())?;
+
+    let project = Project::find_by_id(&conn, project_id)
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| "The project is not created".to_owned())?;
+    let repo = Repository::open(".").map_err(|err| err.to_string())?;
+
+    // The references have already been updated, so failures are reported but not fatal.
+    if let Err(err) = hooks::post_receive(&*conn, &project, user_id, &repo, &updates) {
+        let _ = writeln!(&mut std::io::stderr(), "Failed to trigger webhooks: {}", err);
+    }
+    Ok(())
+}
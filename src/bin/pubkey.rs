@@ -1,18 +1,17 @@
 extern crate gallium;
 extern crate diesel;
 extern crate clap;
-extern crate shlex;
 
 use std::env;
 use std::io::Write;
-use std::process::Command;
 use std::os::unix::process::CommandExt;
-use std::path::Path;
 use diesel::prelude::*;
-use gallium::models::{Project, SshKey};
+use gallium::models::SshKey;
+use gallium::models::ssh_keys::PublicKey;
 use gallium::schema::ssh_keys;
 use gallium::config::Config;
 use gallium::db::DB;
+use gallium::ssh;
 
 
 fn build_cli<'a, 'b: 'a>() -> clap::App<'a, 'b> {
@@ -28,6 +27,12 @@ fn build_cli<'a, 'b: 'a>() -> clap::App<'a, 'b> {
         .subcommand(clap::SubCommand::with_name("show").about(
             "Show the list of public keys",
         ))
+        .subcommand(
+            clap::SubCommand::with_name("lookup")
+                .about("Show the public key matching the given one, for AuthorizedKeysCommand of OpenSSH")
+                .arg_from_usage("<key-type>  'The type of the key (%t)'")
+                .arg_from_usage("<key>       'The base64-encoded key (%k)'"),
+        )
 }
 
 fn main() {
@@ -35,10 +40,12 @@ fn main() {
     let err = match matches.subcommand() {
         ("access", Some(m)) => access(m),
         ("show", Some(m)) => show(m),
+        ("lookup", Some(m)) => lookup(m),
         _ => unreachable!(),
     };
     if let Err(err) = err {
-        println!("Failed with: {}", err);
+        // The standard output of `lookup` is read by OpenSSH as lines of `authorized_keys`.
+        let _ = writeln!(&mut std::io::stderr(), "Failed with: {}", err);
         std::process::exit(1);
     }
 }
@@ -51,21 +58,9 @@ fn access(m: &clap::ArgMatches) -> Result<(), String> {
     let s = env::var("SSH_ORIGINAL_COMMAND").map_err(
         |err| err.to_string(),
     )?;
-    let (action, user, project) = parse_ssh_command(&s)?;
-
-    let project = Project::find_by_id(&conn, (&user, &project))
-        .map_err(|err| err.to_string())?
-        .ok_or_else(|| "The project is not created".to_owned())?;
-    let repo = project.open_repository(&*conn).map_err(
-        |err| err.to_string(),
-    )?;
-
     let user_id = m.value_of("user-id").and_then(|s| s.parse().ok()).unwrap();
-    check_scope(&action, user_id, &project)?;
 
-    let err = Command::new(action)
-        .arg(repo.path().to_str().unwrap())
-        .exec();
+    let err = ssh::prepare_command(&*conn, &s, user_id)?.exec();
     let _ = writeln!(&mut std::io::stderr(), "failed to exec: {:?}", err);
     std::process::exit(1);
 }
@@ -79,63 +74,43 @@ fn show(_m: &clap::ArgMatches) -> Result<(), String> {
 
     let keys: Vec<SshKey> = ssh_keys::table.load(&*conn).unwrap();
     for key in keys {
-        println!(
-            "command=\"/opt/gallium/bin/pubkey access --user-id={}\" {}",
-            key.user_id,
-            key.key
-        );
+        match authorized_key_line(&key) {
+            Ok(line) => println!("{}", line),
+            Err(err) => {
+                let _ = writeln!(&mut std::io::stderr(), "skipped the key {}: {}", key.id, err);
+            }
+        }
     }
 
     Ok(())
 }
 
-fn parse_ssh_command(s: &str) -> Result<(String, String, String), String> {
-    let command = shlex::split(s).ok_or_else(|| {
-        "failed to parse SSH_ORIGINAL_COMMAND".to_string()
-    })?;
-
-    if command.len() < 1 {
-        return Err("command is not given".to_string());
-    } else if command.len() < 2 {
-        return Err("Missing repository".to_string());
-    }
-
-    // validate action
-    let action = &command[0];
-    if action != "git-receive-pack" && action != "git-upload-pack" && action != "git-upload-archive" {
-        return Err("Permission denied".to_string());
-    }
-
-    // validate repository
-    let repository = &command[1];
-    if Path::new(repository).is_absolute() || repository.starts_with("./") || repository.starts_with("../") {
-        return Err("incorrect repository path".to_string());
-    }
-
-    let elems: Vec<_> = repository.split("/").collect();
-    if elems.len() != 2 {
-        return Err("Incorrect repository path".to_owned());
-    }
-
-    let user = &elems[0];
-    let project = &elems[1];
+fn lookup(m: &clap::ArgMatches) -> Result<(), String> {
+    let config = Config::load().map_err(|err| err.to_string())?;
+    let db = DB::new(&config.database_url).map_err(|err| err.to_string())?;
+    let conn = db.get_db_conn().map_err(|err| err.to_string())?;
 
-    if !project.ends_with(".git") {
-        return Err("The repository URL should be end with '.git'".to_owned());
+    let key_type = m.value_of("key-type").unwrap();
+    let key = m.value_of("key").unwrap();
+    // Nothing is printed for unknown keys, so that OpenSSH rejects them.
+    if let Some(key) = SshKey::find_by_public_key(&*conn, key_type, key).map_err(|err| err.to_string())? {
+        println!("{}", authorized_key_line(&key)?);
     }
-    let project = project.trim_right_matches(".git");
 
-    Ok((action.to_owned(), (*user).to_owned(), project.to_owned()))
+    Ok(())
 }
 
-fn check_scope(action: &str, user_id: i32, project: &Project) -> Result<(), String> {
-    match action {
-        "git-receive-pack" => {
-            if project.user_id != user_id {
-                return Err("Permission denied".to_string());
-            }
-        }
-        _ => (),
-    }
-    Ok(())
+/// Returns the line of `authorized_keys` which forces the key to run `pubkey access`.
+///
+/// The key is parsed again and written in the normalized form, so that malformed keys stored in
+/// the database never inject any options.
+fn authorized_key_line(key: &SshKey) -> Result<String, String> {
+    let public_key = PublicKey::parse(&key.key)?;
+    let exe = env::current_exe().map_err(|err| err.to_string())?;
+    Ok(format!(
+        "command=\"{} access --user-id={}\",no-port-forwarding,no-X11-forwarding,no-agent-forwarding,no-pty {}",
+        exe.display(),
+        key.user_id,
+        public_key
+    ))
 }
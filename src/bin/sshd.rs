@@ -0,0 +1,310 @@
+//! A built-in SSH server for git, which can be used in place of OpenSSH and `pubkey access`.
+//!
+//! Clients are authenticated by the public keys registered in `ssh_keys` regardless of the user
+//! name, and only the git commands accepted by `ssh::parse_ssh_command` are allowed. ECDSA keys
+//! are not supported yet, so their owners still need OpenSSH.
+//!
+//! ```text
+//! $ ssh-keygen -t ed25519 -N '' -f ssh_host_ed25519_key
+//! $ sshd --listen 127.0.0.1:2222 --host-key ssh_host_ed25519_key
+//! $ GIT_SSH_COMMAND='ssh -p 2222' git clone git@127.0.0.1:<user>/<project>.git
+//! ```
+//!
+//! `scripts/test-sshd.sh` clones and pushes through a running server.
+
+extern crate gallium;
+extern crate clap;
+extern crate futures;
+extern crate thrussh;
+extern crate thrussh_keys;
+extern crate tokio;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{Read, Write};
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use futures::future::{self, BoxFuture, Future, FutureExt};
+use thrussh::{ChannelId, CryptoVec, MethodSet};
+use thrussh::server::{self, Auth, Handle, Session};
+use thrussh_keys::PublicKeyBase64;
+use thrussh_keys::key;
+use tokio::runtime::{self, Runtime};
+use tokio::sync::mpsc;
+use tokio::task;
+use gallium::config::Config;
+use gallium::db::DB;
+use gallium::models::SshKey;
+use gallium::models::ssh_keys::PublicKey;
+use gallium::ssh;
+
+
+/// The extended data type of the standard error.
+const EXTENDED_DATA_STDERR: u32 = 1;
+
+/// The maximum number of packets from the client which are buffered for the standard input of git.
+///
+/// Once it is reached, the connection stops reading from the client until git consumes the input,
+/// so the client is throttled by the window of the channel.
+const MAX_PENDING_PACKETS: usize = 16;
+
+
+fn build_cli<'a, 'b: 'a>() -> clap::App<'a, 'b> {
+    clap::App::new("sshd")
+        .about("SSH server for git")
+        .arg_from_usage("--listen=[addr]    'The address to listen on (default: 0.0.0.0:2222)'")
+        .arg_from_usage("--host-key=<path>  'The path of the private host key'")
+}
+
+fn main() {
+    let ref matches = build_cli().get_matches();
+    if let Err(err) = run(matches) {
+        let _ = writeln!(&mut std::io::stderr(), "{}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(m: &clap::ArgMatches) -> Result<(), String> {
+    let config = Config::load().map_err(|err| err.to_string())?;
+    env::set_current_dir(&config.repository_root).map_err(
+        |err| err.to_string(),
+    )?;
+    let db = DB::new(&config.database_url).map_err(|err| err.to_string())?;
+
+    let host_key = thrussh_keys::load_secret_key(m.value_of("host-key").unwrap(), None)
+        .map_err(|err| format!("failed to load the host key: {}", err))?;
+    let mut ssh_config = server::Config::default();
+    ssh_config.keys.push(host_key);
+    ssh_config.methods = MethodSet::PUBLICKEY;
+    ssh_config.auth_rejection_time = Duration::from_secs(1);
+
+    let addr = m.value_of("listen").unwrap_or("0.0.0.0:2222");
+    let rt = Runtime::new().map_err(|err| err.to_string())?;
+    rt.block_on(server::run(Arc::new(ssh_config), addr, Server::with_db(db)))
+        .map_err(|err| err.to_string())
+}
+
+
+/// The handler of a connection.
+#[derive(Clone)]
+struct Server {
+    db: DB,
+    /// The user of the key accepted by the last call of `auth_publickey`.
+    ///
+    /// thrussh accepts a signature only from the key of the last accepted call, which is either the
+    /// signed request itself or the probe answered with `SSH_MSG_USERAUTH_PK_OK`, and forgets the
+    /// probe once any request is rejected. So this is the owner of the verified key when the
+    /// authentication succeeds, and is cleared on rejections so that no earlier probe survives.
+    user_id: Option<i32>,
+    /// The standard inputs of the git processes running in the channels.
+    stdins: Arc<Mutex<HashMap<ChannelId, mpsc::Sender<Vec<u8>>>>>,
+}
+
+impl Server {
+    fn with_db(db: DB) -> Self {
+        Server {
+            db: db,
+            user_id: None,
+            stdins: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Spawns the git process, whose input and output are forwarded through the channel.
+    ///
+    /// This blocks on the database and must not be called on the event loop.
+    fn exec(
+        db: &DB,
+        user_id: Option<i32>,
+        command: &str,
+    ) -> Result<(Child, mpsc::Sender<Vec<u8>>), String> {
+        let user_id = user_id.ok_or_else(|| "Permission denied".to_owned())?;
+        let conn = db.get_db_conn().map_err(|err| err.to_string())?;
+        let mut child = ssh::prepare_command(&*conn, command, user_id)?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("failed to exec: {}", err))?;
+
+        // The input is written by another thread, so that the event loop is never blocked by git.
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(MAX_PENDING_PACKETS);
+        let mut stdin = child.stdin.take().unwrap();
+        thread::spawn(move || while let Some(data) = rx.blocking_recv() {
+            if stdin.write_all(&data).is_err() {
+                break;
+            }
+        });
+        Ok((child, tx))
+    }
+}
+
+/// Looks up the owner of the key offered by the client.
+fn find_user(db: &DB, blob: Vec<u8>) -> Result<Option<i32>, String> {
+    let key = PublicKey::from_blob(blob)?;
+    let conn = db.get_db_conn().map_err(|err| err.to_string())?;
+    let key = SshKey::find_by_key(&*conn, &key).map_err(|err| err.to_string())?;
+    Ok(key.map(|key| key.user_id))
+}
+
+impl server::Server for Server {
+    type Handler = Self;
+
+    fn new(&mut self, _peer_addr: Option<::std::net::SocketAddr>) -> Self {
+        Server::with_db(self.db.clone())
+    }
+}
+
+impl server::Handler for Server {
+    type Error = thrussh::Error;
+
+    fn auth_publickey(
+        self,
+        _user: &str,
+        public_key: &key::PublicKey,
+    ) -> impl Future<Output = Result<(Self, Auth), Self::Error>> + Send {
+        let db = self.db.clone();
+        let blob = public_key.public_key_bytes();
+        task::spawn_blocking(move || find_user(&db, blob)).map(move |found| {
+            let mut this = self;
+            this.user_id = match found {
+                Ok(Ok(user_id)) => user_id,
+                Ok(Err(err)) => {
+                    let _ = writeln!(&mut std::io::stderr(), "failed to authenticate: {}", err);
+                    None
+                }
+                Err(err) => {
+                    let _ = writeln!(&mut std::io::stderr(), "failed to authenticate: {}", err);
+                    None
+                }
+            };
+            let auth = if this.user_id.is_some() {
+                Auth::Accept
+            } else {
+                Auth::Reject
+            };
+            Ok((this, auth))
+        })
+    }
+
+    fn exec_request(
+        self,
+        channel: ChannelId,
+        data: &[u8],
+        session: Session,
+    ) -> impl Future<Output = Result<(Self, Session), Self::Error>> + Send {
+        let command = String::from_utf8_lossy(data).into_owned();
+        let db = self.db.clone();
+        let user_id = self.user_id;
+        task::spawn_blocking(move || Server::exec(&db, user_id, &command)).map(move |spawned| {
+            let mut session = session;
+            match spawned {
+                Ok(Ok((child, stdin))) => {
+                    self.stdins.lock().unwrap().insert(channel, stdin);
+                    spawn_forwarders(child, session.handle(), channel);
+                }
+                Ok(Err(message)) => close_with_error(&mut session, channel, &message),
+                Err(err) => close_with_error(&mut session, channel, &err.to_string()),
+            }
+            Ok((self, session))
+        })
+    }
+
+    fn data(
+        self,
+        channel: ChannelId,
+        data: &[u8],
+        session: Session,
+    ) -> impl Future<Output = Result<(Self, Session), Self::Error>> + Send {
+        let stdin = self.stdins.lock().unwrap().get(&channel).cloned();
+        let sent: BoxFuture<()> = match stdin {
+            // Waiting for the room of the buffer holds the connection back from reading any more
+            // data, until git consumes its input.
+            Some(stdin) => {
+                let data = data.to_vec();
+                stdin
+                    .reserve_owned()
+                    .map(move |permit| if let Ok(permit) = permit {
+                        permit.send(data);
+                    })
+                    .boxed()
+            }
+            None => future::ready(()).boxed(),
+        };
+        sent.map(move |_| Ok((self, session)))
+    }
+
+    fn channel_eof(
+        self,
+        channel: ChannelId,
+        session: Session,
+    ) -> impl Future<Output = Result<(Self, Session), Self::Error>> + Send {
+        // Dropping the sender closes the standard input of git, once the pending input is written.
+        self.stdins.lock().unwrap().remove(&channel);
+        future::ready(Ok((self, session)))
+    }
+}
+
+
+fn close_with_error(session: &mut Session, channel: ChannelId, message: &str) {
+    session.extended_data(
+        channel,
+        EXTENDED_DATA_STDERR,
+        CryptoVec::from_slice(format!("{}\n", message).as_bytes()),
+    );
+    session.exit_status_request(channel, 1);
+    session.eof(channel);
+    session.close(channel);
+}
+
+/// Forwards the output of git to the channel, and reports the exit status once git exits.
+fn spawn_forwarders(mut child: Child, handle: Handle, channel: ChannelId) {
+    let rt = runtime::Handle::current();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    thread::spawn(move || {
+        let stderr = {
+            let rt = rt.clone();
+            let handle = handle.clone();
+            thread::spawn(move || forward(&rt, stderr, handle, channel, Some(EXTENDED_DATA_STDERR)))
+        };
+        let mut handle = handle;
+        let connected = forward(&rt, stdout, handle.clone(), channel, None);
+        let _ = stderr.join();
+
+        let status = child.wait().ok().and_then(|status| status.code()).unwrap_or(1);
+        if connected {
+            let _ = rt.block_on(handle.exit_status_request(channel, status as u32));
+            let _ = rt.block_on(handle.eof(channel));
+            let _ = rt.block_on(handle.close(channel));
+        }
+    });
+}
+
+/// Copies the output to the channel until EOF, and returns whether the channel is still open.
+///
+/// Sending through the handle waits for the connection, so git is throttled by the client.
+fn forward<R: Read>(
+    rt: &runtime::Handle,
+    mut output: R,
+    mut handle: Handle,
+    channel: ChannelId,
+    ext: Option<u32>,
+) -> bool {
+    let mut buf = [0u8; 32768];
+    loop {
+        let n = match output.read(&mut buf) {
+            Ok(0) | Err(_) => return true,
+            Ok(n) => n,
+        };
+        let data = CryptoVec::from_slice(&buf[..n]);
+        let sent = match ext {
+            Some(ext) => rt.block_on(handle.extended_data(channel, ext, data)),
+            None => rt.block_on(handle.data(channel, data)),
+        };
+        if sent.is_err() {
+            return false;
+        }
+    }
+}
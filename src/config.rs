@@ -1,7 +1,9 @@
 use std::{env, fs, path};
 use std::sync::Arc;
+use std::time::Duration;
 use serde_json;
 use error::AppResult;
+use rpc::Timeouts;
 use iron::{Request, IronResult, BeforeMiddleware};
 use iron::typemap::Key;
 
@@ -10,6 +12,32 @@ pub struct Config {
     pub database_url: String,
     pub repository_root: path::PathBuf,
     pub jwt_secret: String,
+    /// The maximum size of blobs whose content is embedded into JSON responses, in bytes.
+    #[serde(default = "default_max_inline_blob_size")]
+    pub max_inline_blob_size: usize,
+    /// The maximum duration of git processes serving fetches and pushes over HTTP, in seconds.
+    #[serde(default = "default_rpc_timeout")]
+    pub rpc_timeout: u64,
+    /// The maximum duration of git processes serving fetches and pushes over HTTP without any input
+    /// or output, in seconds.
+    #[serde(default = "default_rpc_idle_timeout")]
+    pub rpc_idle_timeout: u64,
+    /// The path of the `hook` binary called by the hooks installed into the repositories.
+    /// Defaults to `hook` next to the server executable.
+    #[serde(default)]
+    pub hook_path: Option<path::PathBuf>,
+}
+
+fn default_max_inline_blob_size() -> usize {
+    1024 * 1024
+}
+
+fn default_rpc_timeout() -> u64 {
+    60 * 60
+}
+
+fn default_rpc_idle_timeout() -> u64 {
+    10 * 60
 }
 
 impl Config {
@@ -24,8 +52,30 @@ impl Config {
         Ok(config)
     }
 
-    pub fn repository_path(&self, user: &str, project: &str) -> path::PathBuf {
-        self.repository_root.join(user).join(project)
+    /// Returns the path of the repository, where `namespace` is the name of either a user or an organization.
+    pub fn repository_path(&self, namespace: &str, project: &str) -> path::PathBuf {
+        self.repository_root.join(namespace).join(project)
+    }
+
+    /// Returns the path of the `hook` binary called by the hooks installed into the repositories.
+    ///
+    /// This must be called only by the server, so that the hooks always point to the same binary.
+    pub fn hook_path(&self) -> AppResult<path::PathBuf> {
+        if let Some(ref hook_path) = self.hook_path {
+            return Ok(hook_path.clone());
+        }
+        env::current_exe()?
+            .parent()
+            .map(|dir| dir.join("hook"))
+            .ok_or_else(|| "failed to get the directory of executable".into())
+    }
+
+    /// Returns the timeouts of git processes serving fetches and pushes over HTTP.
+    pub fn rpc_timeouts(&self) -> Timeouts {
+        Timeouts {
+            total: Duration::from_secs(self.rpc_timeout),
+            idle: Duration::from_secs(self.rpc_idle_timeout),
+        }
     }
 }
 
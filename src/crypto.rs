@@ -25,3 +25,24 @@ pub fn generate_sha1_random() -> String {
             acc
         })
 }
+
+/// Generates a random secret suitable for use as an access token.
+pub fn generate_token() -> String {
+    use ring::rand::SecureRandom;
+    let rand = ring::rand::SystemRandom::new();
+    let mut buf = vec![0u8; 32];
+    rand.fill(&mut buf).unwrap();
+    to_hex(&buf)
+}
+
+/// Returns the hex-encoded SHA-256 digest of the token, which is the only form stored in database.
+pub fn hash_token(token: &str) -> String {
+    to_hex(ring::digest::digest(&ring::digest::SHA256, token.as_bytes()).as_ref())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut acc, c| {
+        acc.push_str(&format!("{:02x}", c));
+        acc
+    })
+}
@@ -13,5 +13,26 @@ error_chain! {
         JsonWebToken(::jsonwebtoken::errors::Error);
         Bcrypt(::bcrypt::BcryptError);
         Hyper(::hyper::Error);
+        Zip(::zip::result::ZipError);
+    }
+
+    errors {
+        // The requested object does not exist in the repository.
+        NotFound(message: String) {
+            description("not found")
+            display("{}", message)
+        }
+
+        // The requested change conflicts with the current state of the repository.
+        Conflict(message: String) {
+            description("conflict")
+            display("{}", message)
+        }
+
+        // The request to the repository is malformed.
+        Invalid(message: String) {
+            description("invalid request")
+            display("{}", message)
+        }
     }
 }
@@ -0,0 +1,195 @@
+//! Server-side git hooks, invoked through the `hook` binary installed by `Repository::install_hooks`.
+
+use std::process::Command;
+use diesel::pg::PgConnection;
+
+use error::{AppResult, AppError};
+use models::{LfsLock, Project, Repository, User};
+use models::protected_branches::ProtectedBranch;
+use models::webhooks::{EVENT_PUSH, EVENT_TAG_PUSH};
+use webhooks;
+
+
+/// The environment variable which carries the ID of the project being pushed to.
+pub const ENV_PROJECT_ID: &'static str = "GALLIUM_PROJECT_ID";
+
+/// The environment variable which carries the ID of the authenticated user.
+pub const ENV_USER_ID: &'static str = "GALLIUM_USER_ID";
+
+/// The object name which represents a nonexistent reference in updates.
+pub const ZERO_OID: &'static str = "0000000000000000000000000000000000000000";
+
+/// The maximum number of commits embedded into a push event.
+const MAX_PUSH_COMMITS: usize = 20;
+
+
+/// Checks an update of a reference requested by `git-receive-pack`.
+///
+/// Returns the message to be reported to the git client if the update is rejected.
+pub fn check_update(
+    conn: &PgConnection,
+    project: &Project,
+    user_id: Option<i32>,
+    refname: &str,
+    old: &str,
+    new: &str,
+) -> AppResult<Option<String>> {
+    if refname.starts_with("refs/heads/") {
+        let branch = &refname["refs/heads/".len()..];
+        if let Some(message) = check_protected_branch(conn, project, user_id, branch, old, new)? {
+            return Ok(Some(message));
+        }
+    }
+    // Locked files must not be modified through any reference, e.g. tags or `refs/for/*`.
+    check_locks(conn, project, user_id, old, new)
+}
+
+fn check_protected_branch(
+    conn: &PgConnection,
+    project: &Project,
+    user_id: Option<i32>,
+    branch: &str,
+    old: &str,
+    new: &str,
+) -> AppResult<Option<String>> {
+    let rule = match ProtectedBranch::find_matching(conn, project.id, branch)? {
+        Some(rule) => rule,
+        None => return Ok(None),
+    };
+
+    let role = match user_id {
+        Some(user_id) => project.role_of(conn, user_id)?,
+        None => None,
+    };
+    let is_deletion = new == ZERO_OID;
+    let is_force = old != ZERO_OID && !is_deletion && !is_ancestor(old, new)?;
+
+    Ok(rule.check_update(branch, role, is_deletion, is_force).err())
+}
+
+/// Rejects the update if the pushed commits modify files locked by other users.
+fn check_locks(
+    conn: &PgConnection,
+    project: &Project,
+    user_id: Option<i32>,
+    old: &str,
+    new: &str,
+) -> AppResult<Option<String>> {
+    if new == ZERO_OID {
+        return Ok(None);
+    }
+    let locks: Vec<_> = LfsLock::load_by_project(conn, project.id)?
+        .into_iter()
+        .filter(|&(_, ref lock)| Some(lock.user_id) != user_id)
+        .collect();
+    if locks.is_empty() {
+        return Ok(None);
+    }
+
+    let old = if old == ZERO_OID { None } else { Some(old) };
+    for path in changed_paths(old, new)? {
+        if let Some(&(ref owner, _)) = locks.iter().find(|&&(_, ref lock)| lock.path == path) {
+            return Ok(Some(format!("The file '{}' is locked by {}", path, owner.name)));
+        }
+    }
+    Ok(None)
+}
+
+
+/// Notifies the updates of references after `git-receive-pack` has finished.
+///
+/// Each element of `updates` is a tuple of `(refname, old, new)`.
+pub fn post_receive(
+    conn: &PgConnection,
+    project: &Project,
+    user_id: Option<i32>,
+    repo: &Repository,
+    updates: &[(String, String, String)],
+) -> AppResult<()> {
+    let project_payload = webhooks::project_payload(conn, project)?;
+    let user_payload = match user_id {
+        Some(user_id) => User::find_by_id(conn, user_id)?.map(|user| webhooks::user_payload(&user)),
+        None => None,
+    };
+
+    for &(ref refname, ref old, ref new) in updates {
+        let event = if refname.starts_with("refs/tags/") {
+            EVENT_TAG_PUSH
+        } else if refname.starts_with("refs/heads/") {
+            EVENT_PUSH
+        } else {
+            continue;
+        };
+
+        let created = old == ZERO_OID;
+        let deleted = new == ZERO_OID;
+        let commits = if deleted {
+            Vec::new()
+        } else {
+            let old = if created { None } else { Some(old.as_str()) };
+            repo.list_pushed_commits(old, new, MAX_PUSH_COMMITS)?
+        };
+
+        webhooks::trigger(
+            conn,
+            project.id,
+            event,
+            json!({
+                "project": project_payload,
+                "user": user_payload,
+                "ref": refname,
+                "before": old,
+                "after": new,
+                "created": created,
+                "deleted": deleted,
+                "commits": commits,
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+
+/// Lists the paths modified by the commits introduced by an update from `old` to `new`.
+///
+/// For a newly created reference (`old` is `None`), commits reachable from other references are
+/// excluded. Merge commits are compared with each of their parents, so that the files modified
+/// while resolving conflicts are also listed. Like `is_ancestor`, this runs the git command so that
+/// the quarantined objects are visible.
+fn changed_paths(old: Option<&str>, new: &str) -> AppResult<Vec<String>> {
+    let range = match old {
+        Some(old) => vec![format!("{}..{}", old, new)],
+        None => vec![new.to_owned(), "--not".to_owned(), "--all".to_owned()],
+    };
+    let output = Command::new("/usr/bin/git")
+        .args(&["log", "--format=", "--name-only", "--no-renames", "-m", "-z"])
+        .args(&range)
+        .output()?;
+    if !output.status.success() {
+        return Err(AppError::from("`git log` exited with non-zero status"));
+    }
+    let mut paths: Vec<String> = output
+        .stdout
+        .split(|&b| b == b'\0')
+        .map(|path| String::from_utf8_lossy(path).trim_matches('\n').to_owned())
+        .filter(|path| !path.is_empty())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Returns whether `old` is an ancestor of `new`, i.e. the update is a fast-forward.
+///
+/// This is delegated to the git command rather than libgit2, since objects received by
+/// `git-receive-pack` are still in the quarantine directory while hooks are running.
+fn is_ancestor(old: &str, new: &str) -> AppResult<bool> {
+    let status = Command::new("/usr/bin/git")
+        .args(&["merge-base", "--is-ancestor", old, new])
+        .status()?;
+    match status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(AppError::from("`git merge-base` exited with unexpected status")),
+    }
+}
@@ -0,0 +1,99 @@
+//! Storage of Git LFS objects.
+//!
+//! The content of objects is stored on the filesystem under `<repository_root>/.lfs/<project id>`,
+//! in the same layout as the local storage of git-lfs (`<oid[0..2]>/<oid[2..4]>/<oid>`). Uploaded
+//! objects are also recorded in the database as `LfsObject`, which is used for the storage accounting.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use ring::digest;
+
+use crypto;
+use error::{AppResult, AppErrorKind};
+
+
+/// Returns whether the OID is a hex-encoded SHA-256 hash, the only one supported by git-lfs.
+pub fn is_valid_oid(oid: &str) -> bool {
+    oid.len() == 64 &&
+        oid.bytes().all(|b| match b {
+            b'0'...b'9' | b'a'...b'f' => true,
+            _ => false,
+        })
+}
+
+/// Returns the directory which contains the objects of the project.
+pub fn project_dir(root: &Path, project_id: i32) -> PathBuf {
+    root.join(".lfs").join(project_id.to_string())
+}
+
+/// Returns the path of the object, whose OID must be valid.
+pub fn object_path(root: &Path, project_id: i32, oid: &str) -> PathBuf {
+    project_dir(root, project_id)
+        .join(&oid[0..2])
+        .join(&oid[2..4])
+        .join(oid)
+}
+
+/// Stores the object read from `body`, after verifying its size and OID.
+///
+/// The content is written into a temporary file first, so that incomplete or corrupted objects are
+/// never served.
+pub fn store(root: &Path, project_id: i32, oid: &str, size: i64, body: &mut Read) -> AppResult<()> {
+    let path = object_path(root, project_id, oid);
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    let tmp_path = path.with_file_name(format!("{}.{}.tmp", oid, crypto::generate_sha1_random()));
+    match write_verified(&tmp_path, oid, size, body) {
+        Ok(()) => fs::rename(&tmp_path, &path).map_err(Into::into),
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(err)
+        }
+    }
+}
+
+fn write_verified(path: &Path, oid: &str, size: i64, body: &mut Read) -> AppResult<()> {
+    let mut file = fs::File::create(path)?;
+    let mut context = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    let mut written = 0i64;
+    loop {
+        let len = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        written += len as i64;
+        if written > size {
+            bail!(AppErrorKind::Invalid(
+                format!("The object is larger than the declared size {}", size),
+            ));
+        }
+        context.update(&buf[..len]);
+        file.write_all(&buf[..len])?;
+    }
+    if written != size {
+        bail!(AppErrorKind::Invalid(format!(
+            "The size of the object does not match: expected {}, but got {}",
+            size,
+            written
+        )));
+    }
+    if crypto::to_hex(context.finish().as_ref()) != oid {
+        bail!(AppErrorKind::Invalid(
+            format!("The content of the object does not match the OID {}", oid),
+        ));
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Removes all objects of the project.
+pub fn remove_project(root: &Path, project_id: i32) -> io::Result<()> {
+    match fs::remove_dir_all(project_dir(root, project_id)) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
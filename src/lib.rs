@@ -21,24 +21,35 @@ extern crate users;
 extern crate git2;
 #[macro_use]
 extern crate hyper;
+extern crate hyper_native_tls;
 extern crate jsonwebtoken;
 extern crate uuid;
 #[macro_use]
 extern crate error_chain;
+extern crate md5;
 extern crate ring;
+extern crate shlex;
+extern crate tar;
 extern crate url;
+extern crate zip;
 extern crate iron_router_ext;
 #[macro_use]
 extern crate iron_router_codegen;
 
+pub mod auth;
 pub mod db;
 pub mod crypto;
 pub mod config;
 pub mod error;
+pub mod hooks;
+pub mod lfs;
 pub mod models;
 pub mod routes;
+pub mod rpc;
 pub mod schema;
 pub mod server;
+pub mod ssh;
+pub mod webhooks;
 
 pub use db::DB;
 pub use config::Config;
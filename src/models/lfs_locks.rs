@@ -0,0 +1,77 @@
+use chrono::NaiveDateTime;
+use diesel::{insert, delete};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use error::AppResult;
+use schema::{users, lfs_locks};
+use super::projects::Project;
+use super::users::User;
+
+
+/// A lock of a file in a project, held by a user through the Git LFS locking API.
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[belongs_to(Project)]
+#[belongs_to(User)]
+pub struct LfsLock {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub project_id: i32,
+    pub user_id: i32,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "lfs_locks"]
+pub struct NewLfsLock {
+    pub project_id: i32,
+    pub user_id: i32,
+    pub path: String,
+}
+
+impl NewLfsLock {
+    pub fn insert(&self, conn: &PgConnection) -> AppResult<LfsLock> {
+        insert(self)
+            .into(lfs_locks::table)
+            .get_result::<LfsLock>(&*conn)
+            .map_err(Into::into)
+    }
+}
+
+impl LfsLock {
+    /// Loads the locks of the project together with their owners, oldest first.
+    pub fn load_by_project(conn: &PgConnection, project_id: i32) -> AppResult<Vec<(User, Self)>> {
+        users::table
+            .inner_join(lfs_locks::table)
+            .filter(lfs_locks::dsl::project_id.eq(project_id))
+            .order(lfs_locks::dsl::id)
+            .load::<(User, LfsLock)>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find(conn: &PgConnection, project_id: i32, id: i32) -> AppResult<Option<(User, Self)>> {
+        users::table
+            .inner_join(lfs_locks::table)
+            .filter(lfs_locks::dsl::project_id.eq(project_id))
+            .filter(lfs_locks::dsl::id.eq(id))
+            .get_result::<(User, LfsLock)>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn find_by_path(conn: &PgConnection, project_id: i32, path: &str) -> AppResult<Option<(User, Self)>> {
+        users::table
+            .inner_join(lfs_locks::table)
+            .filter(lfs_locks::dsl::project_id.eq(project_id))
+            .filter(lfs_locks::dsl::path.eq(path))
+            .get_result::<(User, LfsLock)>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn remove(&self, conn: &PgConnection) -> AppResult<()> {
+        delete(lfs_locks::table.filter(lfs_locks::dsl::id.eq(self.id)))
+            .execute(&*conn)?;
+        Ok(())
+    }
+}
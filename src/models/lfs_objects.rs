@@ -0,0 +1,60 @@
+use chrono::NaiveDateTime;
+use diesel::insert;
+use diesel::expression::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::types::BigInt;
+
+use error::AppResult;
+use schema::lfs_objects;
+use super::projects::Project;
+
+
+/// A Git LFS object uploaded to a project.
+#[derive(Debug, Queryable, Identifiable, Associations)]
+#[belongs_to(Project)]
+pub struct LfsObject {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub project_id: i32,
+    pub oid: String,
+    pub size: i64,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "lfs_objects"]
+pub struct NewLfsObject {
+    pub project_id: i32,
+    pub oid: String,
+    pub size: i64,
+}
+
+impl NewLfsObject {
+    pub fn insert(&self, conn: &PgConnection) -> AppResult<LfsObject> {
+        insert(self)
+            .into(lfs_objects::table)
+            .get_result::<LfsObject>(&*conn)
+            .map_err(Into::into)
+    }
+}
+
+impl LfsObject {
+    pub fn find(conn: &PgConnection, project_id: i32, oid: &str) -> AppResult<Option<Self>> {
+        lfs_objects::table
+            .filter(lfs_objects::dsl::project_id.eq(project_id))
+            .filter(lfs_objects::dsl::oid.eq(oid))
+            .get_result::<LfsObject>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Returns the total size of the objects uploaded to the project, in bytes.
+    pub fn total_size(conn: &PgConnection, project_id: i32) -> AppResult<i64> {
+        // `sum` of `bigint` is `numeric` and null for no rows, so it is cast back to `bigint`.
+        lfs_objects::table
+            .filter(lfs_objects::dsl::project_id.eq(project_id))
+            .select(sql::<BigInt>("coalesce(sum(size), 0)::bigint"))
+            .get_result::<i64>(&*conn)
+            .map_err(Into::into)
+    }
+}
@@ -1,9 +1,23 @@
+pub mod lfs_locks;
+pub mod lfs_objects;
+pub mod organizations;
+pub mod personal_access_tokens;
+pub mod project_members;
 pub mod projects;
+pub mod protected_branches;
 pub mod repository;
 pub mod ssh_keys;
 pub mod users;
+pub mod webhooks;
 
+pub use self::lfs_locks::{LfsLock, NewLfsLock};
+pub use self::lfs_objects::{LfsObject, NewLfsObject};
+pub use self::organizations::{Organization, NewOrganization, OrganizationMember};
+pub use self::personal_access_tokens::PersonalAccessToken;
+pub use self::project_members::ProjectMember;
 pub use self::projects::{Project, NewProject};
+pub use self::protected_branches::{ProtectedBranch, NewProtectedBranch};
 pub use self::repository::Repository;
 pub use self::ssh_keys::{SshKey, NewSshKey};
 pub use self::users::User;
+pub use self::webhooks::{Webhook, NewWebhook, WebhookDelivery};
@@ -0,0 +1,202 @@
+use chrono::NaiveDateTime;
+use diesel::{insert, update, delete};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use error::{AppResult, AppError, AppErrorKind};
+use schema::{users, organizations, organization_members, projects};
+use super::project_members::Role;
+use super::users::User;
+
+
+#[derive(Debug, Queryable, Identifiable, AsChangeset)]
+pub struct Organization {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub name: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable, Deserialize)]
+#[table_name = "organizations"]
+pub struct NewOrganization {
+    pub name: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Names which cannot be used as a namespace, since they collide with the routes of the server.
+const RESERVED_NAMES: &[&str] = &["api"];
+
+/// Checks that the name of a user or an organization matches `^[A-Za-z0-9][A-Za-z0-9_.-]*$` and is
+/// not reserved, since it is used as a directory under `repository_root` (next to `.lfs`) and as a
+/// segment of URLs.
+pub(super) fn validate_namespace(name: &str) -> AppResult<()> {
+    let valid = !name.is_empty() &&
+        name.bytes().enumerate().all(|(i, b)| match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' => true,
+            b'_' | b'.' | b'-' => i > 0,
+            _ => false,
+        });
+    if !valid {
+        bail!(AppErrorKind::Invalid(format!("Invalid name: \"{}\"", name)));
+    }
+    if RESERVED_NAMES.contains(&name) {
+        bail!(AppErrorKind::Invalid(format!("The name \"{}\" is reserved", name)));
+    }
+    Ok(())
+}
+
+impl NewOrganization {
+    /// Creates the organization, and registers the creator as its owner.
+    pub fn insert(&self, conn: &PgConnection, creator_id: i32) -> AppResult<Organization> {
+        validate_namespace(&self.name)?;
+        conn.transaction(|| {
+            let taken = users::table
+                .filter(users::dsl::name.eq(self.name.as_str()))
+                .count()
+                .get_result::<i64>(&*conn)?;
+            if taken > 0 {
+                return Err(AppError::from(
+                    format!("The namespace \"{}\" is already taken", self.name),
+                ));
+            }
+
+            let org = insert(self)
+                .into(organizations::table)
+                .get_result::<Organization>(&*conn)?;
+            OrganizationMember::add(conn, org.id, creator_id, Role::Owner)?;
+            Ok(org)
+        })
+    }
+}
+
+impl Organization {
+    pub fn load_organizations(conn: &PgConnection) -> AppResult<Vec<Self>> {
+        organizations::table
+            .order(organizations::dsl::id)
+            .load::<Organization>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find_by_id(conn: &PgConnection, id: i32) -> AppResult<Option<Self>> {
+        organizations::table
+            .filter(organizations::dsl::id.eq(id))
+            .get_result::<Organization>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn find_by_name(conn: &PgConnection, name: &str) -> AppResult<Option<Self>> {
+        organizations::table
+            .filter(organizations::dsl::name.eq(name))
+            .get_result::<Organization>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn update(&self, conn: &PgConnection, display_name: Option<&str>, description: Option<&str>) -> AppResult<Self> {
+        update(organizations::table.filter(organizations::dsl::id.eq(self.id)))
+            .set((
+                organizations::dsl::display_name.eq(display_name),
+                organizations::dsl::description.eq(description),
+            ))
+            .get_result::<Organization>(&*conn)
+            .map_err(Into::into)
+    }
+
+    /// Removes the organization.
+    ///
+    /// The organization must not own any projects, since their repositories would be left orphaned.
+    pub fn remove(&self, conn: &PgConnection) -> AppResult<()> {
+        let num_projects = projects::table
+            .filter(projects::dsl::organization_id.eq(self.id))
+            .count()
+            .get_result::<i64>(&*conn)?;
+        if num_projects > 0 {
+            return Err(AppError::from("The organization still owns some projects"));
+        }
+
+        delete(organizations::table.filter(organizations::dsl::id.eq(self.id)))
+            .execute(&*conn)?;
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
+#[belongs_to(Organization)]
+#[belongs_to(User)]
+pub struct OrganizationMember {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub organization_id: i32,
+    pub user_id: i32,
+    pub role: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "organization_members"]
+struct NewOrganizationMember<'a> {
+    organization_id: i32,
+    user_id: i32,
+    role: &'a str,
+}
+
+impl OrganizationMember {
+    pub fn role(&self) -> Role {
+        Role::from_str(&self.role).unwrap_or(Role::Guest)
+    }
+
+    pub fn load_by_organization(conn: &PgConnection, organization_id: i32) -> AppResult<Vec<Self>> {
+        organization_members::table
+            .filter(organization_members::dsl::organization_id.eq(organization_id))
+            .order(organization_members::dsl::id)
+            .load::<OrganizationMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn load_by_user(conn: &PgConnection, user_id: i32) -> AppResult<Vec<Self>> {
+        organization_members::table
+            .filter(organization_members::dsl::user_id.eq(user_id))
+            .load::<OrganizationMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find(conn: &PgConnection, organization_id: i32, user_id: i32) -> AppResult<Option<Self>> {
+        organization_members::table
+            .filter(organization_members::dsl::organization_id.eq(organization_id))
+            .filter(organization_members::dsl::user_id.eq(user_id))
+            .get_result::<OrganizationMember>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn add(conn: &PgConnection, organization_id: i32, user_id: i32, role: Role) -> AppResult<Self> {
+        let new_member = NewOrganizationMember {
+            organization_id: organization_id,
+            user_id: user_id,
+            role: role.as_str(),
+        };
+        insert(&new_member)
+            .into(organization_members::table)
+            .get_result::<OrganizationMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn update_role(&self, conn: &PgConnection, role: Role) -> AppResult<Self> {
+        update(organization_members::table.filter(
+            organization_members::dsl::id.eq(self.id),
+        )).set(organization_members::dsl::role.eq(role.as_str()))
+            .get_result::<OrganizationMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn remove(&self, conn: &PgConnection) -> AppResult<()> {
+        delete(organization_members::table.filter(
+            organization_members::dsl::id.eq(self.id),
+        )).execute(&*conn)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use chrono::NaiveDateTime;
+use diesel::{insert, update};
+use diesel::expression::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::types::Bool;
+
+use crypto;
+use error::{AppResult, AppError};
+use schema::{users, personal_access_tokens};
+use super::users::User;
+
+
+pub const SCOPE_READ_REPOSITORY: &'static str = "read_repository";
+pub const SCOPE_WRITE_REPOSITORY: &'static str = "write_repository";
+pub const SCOPE_API: &'static str = "api";
+
+const SCOPES: &'static [&'static str] = &[SCOPE_READ_REPOSITORY, SCOPE_WRITE_REPOSITORY, SCOPE_API];
+
+
+#[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
+#[belongs_to(User)]
+pub struct PersonalAccessToken {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub user_id: i32,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub revoked: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "personal_access_tokens"]
+struct NewToken<'a> {
+    user_id: i32,
+    name: &'a str,
+    token_hash: &'a str,
+    scopes: Vec<String>,
+    expires_at: Option<NaiveDateTime>,
+}
+
+impl PersonalAccessToken {
+    /// Creates a new token and returns it together with the raw secret.
+    ///
+    /// The secret is not stored and cannot be retrieved afterwards.
+    pub fn create(
+        conn: &PgConnection,
+        user_id: i32,
+        name: &str,
+        scopes: &[String],
+        expires_at: Option<NaiveDateTime>,
+    ) -> AppResult<(Self, String)> {
+        if scopes.is_empty() {
+            return Err(AppError::from("At least one scope is required"));
+        }
+        if let Some(scope) = scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+            return Err(AppError::from(format!("Unknown scope: {}", scope)));
+        }
+
+        let secret = crypto::generate_token();
+        let token_hash = crypto::hash_token(&secret);
+        let new_token = NewToken {
+            user_id: user_id,
+            name: name,
+            token_hash: &token_hash,
+            scopes: scopes.to_vec(),
+            expires_at: expires_at,
+        };
+        let token = insert(&new_token)
+            .into(personal_access_tokens::table)
+            .get_result::<PersonalAccessToken>(&*conn)?;
+
+        Ok((token, secret))
+    }
+
+    pub fn load_by_user(conn: &PgConnection, user_id: i32) -> AppResult<Vec<Self>> {
+        personal_access_tokens::table
+            .filter(personal_access_tokens::dsl::user_id.eq(user_id))
+            .order(personal_access_tokens::dsl::id)
+            .load::<PersonalAccessToken>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find_by_id(conn: &PgConnection, id: i32) -> AppResult<Option<Self>> {
+        personal_access_tokens::table
+            .filter(personal_access_tokens::dsl::id.eq(id))
+            .get_result::<PersonalAccessToken>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Finds the owner of an active (neither revoked nor expired) token by its raw secret.
+    pub fn authenticate(conn: &PgConnection, secret: &str) -> AppResult<Option<(User, Self)>> {
+        let token_hash = crypto::hash_token(secret);
+        users::table
+            .inner_join(personal_access_tokens::table)
+            .filter(personal_access_tokens::dsl::token_hash.eq(token_hash))
+            .filter(personal_access_tokens::dsl::revoked.eq(false))
+            .filter(sql::<Bool>(
+                "(personal_access_tokens.expires_at IS NULL OR personal_access_tokens.expires_at > CURRENT_TIMESTAMP)",
+            ))
+            .get_result::<(User, PersonalAccessToken)>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn revoke(&self, conn: &PgConnection) -> AppResult<()> {
+        update(personal_access_tokens::table.filter(
+            personal_access_tokens::dsl::id.eq(self.id),
+        )).set(personal_access_tokens::dsl::revoked.eq(true))
+            .execute(&*conn)?;
+        Ok(())
+    }
+
+    /// Returns whether the token has the given scope.
+    ///
+    /// `write_repository` implies `read_repository`.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| {
+            s == scope || (scope == SCOPE_READ_REPOSITORY && s == SCOPE_WRITE_REPOSITORY)
+        })
+    }
+}
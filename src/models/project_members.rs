@@ -0,0 +1,120 @@
+use chrono::NaiveDateTime;
+use diesel::{insert, update, delete};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use error::AppResult;
+use schema::project_members;
+use super::projects::Project;
+use super::users::User;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Guest,
+    Reporter,
+    Developer,
+    Maintainer,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::Guest => "guest",
+            Role::Reporter => "reporter",
+            Role::Developer => "developer",
+            Role::Maintainer => "maintainer",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "guest" => Some(Role::Guest),
+            "reporter" => Some(Role::Reporter),
+            "developer" => Some(Role::Developer),
+            "maintainer" => Some(Role::Maintainer),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
+#[belongs_to(Project)]
+#[belongs_to(User)]
+pub struct ProjectMember {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub project_id: i32,
+    pub user_id: i32,
+    pub role: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "project_members"]
+struct NewProjectMember<'a> {
+    project_id: i32,
+    user_id: i32,
+    role: &'a str,
+}
+
+impl ProjectMember {
+    pub fn role(&self) -> Role {
+        Role::from_str(&self.role).unwrap_or(Role::Guest)
+    }
+
+    pub fn load_by_project(conn: &PgConnection, project_id: i32) -> AppResult<Vec<Self>> {
+        project_members::table
+            .filter(project_members::dsl::project_id.eq(project_id))
+            .order(project_members::dsl::id)
+            .load::<ProjectMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn load_by_user(conn: &PgConnection, user_id: i32) -> AppResult<Vec<Self>> {
+        project_members::table
+            .filter(project_members::dsl::user_id.eq(user_id))
+            .load::<ProjectMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find(conn: &PgConnection, project_id: i32, user_id: i32) -> AppResult<Option<Self>> {
+        project_members::table
+            .filter(project_members::dsl::project_id.eq(project_id))
+            .filter(project_members::dsl::user_id.eq(user_id))
+            .get_result::<ProjectMember>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn add(conn: &PgConnection, project_id: i32, user_id: i32, role: Role) -> AppResult<Self> {
+        let new_member = NewProjectMember {
+            project_id: project_id,
+            user_id: user_id,
+            role: role.as_str(),
+        };
+        insert(&new_member)
+            .into(project_members::table)
+            .get_result::<ProjectMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn update_role(&self, conn: &PgConnection, role: Role) -> AppResult<Self> {
+        update(project_members::table.filter(
+            project_members::dsl::id.eq(self.id),
+        )).set(project_members::dsl::role.eq(role.as_str()))
+            .get_result::<ProjectMember>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn remove(&self, conn: &PgConnection) -> AppResult<()> {
+        delete(project_members::table.filter(
+            project_members::dsl::id.eq(self.id),
+        )).execute(&*conn)?;
+        Ok(())
+    }
+}
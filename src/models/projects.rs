@@ -1,6 +1,10 @@
+use std::io::{self, Write};
+use std::path::Path;
 use chrono::NaiveDateTime;
-use schema::{users, projects};
+use schema::{users, organizations, projects};
 use super::users::User;
+use super::organizations::{Organization, OrganizationMember};
+use super::project_members::{ProjectMember, Role};
 use super::repository::Repository;
 use error::AppResult;
 
@@ -31,6 +35,47 @@ where
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Readable by anyone, including anonymous users.
+    Public,
+    /// Readable by any authenticated user.
+    Internal,
+    /// Readable only by the members of the project.
+    Private,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility::Public
+    }
+}
+
+impl Visibility {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Visibility::Public => "public",
+            Visibility::Internal => "internal",
+            Visibility::Private => "private",
+        }
+    }
+}
+
+
+/// The level of access to a project.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum AccessLevel {
+    None,
+    /// Allowed to fetch the repository and browse it via the API.
+    Read,
+    /// Allowed to push to the repository.
+    Write,
+    /// Allowed to manage the settings and members of the project.
+    Admin,
+}
+
+
 #[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
 #[belongs_to(User)]
 pub struct Project {
@@ -39,9 +84,91 @@ pub struct Project {
     pub user_id: i32,
     pub name: String,
     pub description: Option<String>,
+    pub visibility: String,
+    pub organization_id: Option<i32>,
 }
 
 impl Project {
+    pub fn visibility(&self) -> Visibility {
+        match self.visibility.as_str() {
+            "public" => Visibility::Public,
+            "internal" => Visibility::Internal,
+            _ => Visibility::Private,
+        }
+    }
+
+    /// Resolves the effective role of the user in the project.
+    ///
+    /// The creator of a personal project is its owner. For projects owned by an organization,
+    /// the higher of the roles in the organization and in the project is taken.
+    pub fn role_of(&self, conn: &PgConnection, user_id: i32) -> AppResult<Option<Role>> {
+        if self.organization_id.is_none() && self.user_id == user_id {
+            return Ok(Some(Role::Owner));
+        }
+        let project_role = ProjectMember::find(conn, self.id, user_id)?.map(|m| m.role());
+        let org_role = match self.organization_id {
+            Some(organization_id) => OrganizationMember::find(conn, organization_id, user_id)?.map(|m| m.role()),
+            None => None,
+        };
+        Ok(max_role(project_role, org_role))
+    }
+
+    /// Resolves the access level of the user (or an anonymous user if `None`) to the project.
+    ///
+    /// This is the single place which decides the permissions, shared by the API, git over HTTP
+    /// and git over SSH.
+    pub fn access_level(&self, conn: &PgConnection, user_id: Option<i32>) -> AppResult<AccessLevel> {
+        let role = match user_id {
+            Some(user_id) => self.role_of(conn, user_id)?,
+            None => None,
+        };
+        Ok(self.resolve_access_level(user_id.is_some(), role))
+    }
+
+    fn resolve_access_level(&self, is_authenticated: bool, role: Option<Role>) -> AccessLevel {
+        match role {
+            Some(Role::Owner) | Some(Role::Maintainer) => return AccessLevel::Admin,
+            Some(Role::Developer) => return AccessLevel::Write,
+            Some(Role::Reporter) => return AccessLevel::Read,
+            Some(Role::Guest) | None => (),
+        }
+        match self.visibility() {
+            Visibility::Public => AccessLevel::Read,
+            Visibility::Internal if is_authenticated => AccessLevel::Read,
+            _ => AccessLevel::None,
+        }
+    }
+
+    /// Loads all projects which the user (or an anonymous user if `None`) is allowed to read.
+    pub fn load_readable(conn: &PgConnection, user_id: Option<i32>) -> AppResult<Vec<Self>> {
+        let (members, org_members) = match user_id {
+            Some(user_id) => (
+                ProjectMember::load_by_user(conn, user_id)?,
+                OrganizationMember::load_by_user(conn, user_id)?,
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        let projects = projects::table
+            .order(projects::dsl::id)
+            .load::<Project>(&*conn)?
+            .into_iter()
+            .filter(|project| {
+                let role = if project.organization_id.is_none() && user_id == Some(project.user_id) {
+                    Some(Role::Owner)
+                } else {
+                    let project_role = members.iter().find(|m| m.project_id == project.id).map(|m| m.role());
+                    let org_role = org_members
+                        .iter()
+                        .find(|m| Some(m.organization_id) == project.organization_id)
+                        .map(|m| m.role());
+                    max_role(project_role, org_role)
+                };
+                project.resolve_access_level(user_id.is_some(), role) >= AccessLevel::Read
+            })
+            .collect();
+        Ok(projects)
+    }
+
     pub fn find_by_id<I: Into<ProjectID>>(conn: &PgConnection, id: I) -> AppResult<Option<Self>> {
         match id.into() {
             ProjectID::Number(id) => {
@@ -51,11 +178,20 @@ impl Project {
                     .optional()
                     .map_err(Into::into)
             }
-            ProjectID::Path(ref user, ref project) => {
+            ProjectID::Path(ref namespace, ref project) => {
+                if let Some(org) = Organization::find_by_name(conn, namespace)? {
+                    return projects::table
+                        .filter(projects::dsl::organization_id.eq(org.id))
+                        .filter(projects::dsl::name.eq(project.as_str()))
+                        .get_result::<Project>(&*conn)
+                        .optional()
+                        .map_err(Into::into);
+                }
                 users::table
                     .inner_join(projects::table)
-                    .filter(users::dsl::name.eq(user.as_str()))
+                    .filter(users::dsl::name.eq(namespace.as_str()))
                     .filter(projects::dsl::name.eq(project.as_str()))
+                    .filter(projects::dsl::organization_id.is_null())
                     .get_result::<(User, Project)>(&*conn)
                     .map(|(_, project)| project)
                     .optional()
@@ -64,52 +200,110 @@ impl Project {
         }
     }
 
+    /// Returns the name of the namespace (the owner user or organization) of the project.
+    pub fn namespace(&self, conn: &PgConnection) -> AppResult<String> {
+        match self.organization_id {
+            Some(organization_id) => {
+                organizations::table
+                    .filter(organizations::dsl::id.eq(organization_id))
+                    .select(organizations::dsl::name)
+                    .get_result::<String>(conn)
+                    .map_err(Into::into)
+            }
+            None => {
+                users::table
+                    .filter(users::dsl::id.eq(self.user_id))
+                    .select(users::dsl::name)
+                    .get_result::<String>(conn)
+                    .map_err(Into::into)
+            }
+        }
+    }
+
     pub fn open_repository(&self, conn: &PgConnection) -> AppResult<Repository> {
-        let user = users::table
-            .filter(users::dsl::id.eq(self.user_id))
-            .get_result::<User>(conn)?;
-        Repository::open(format!("{}/{}", user.name, self.name))
+        let namespace = self.namespace(conn)?;
+        Repository::open(format!("{}/{}", namespace, self.name))
     }
 
-    pub fn init_repository(&self, conn: &PgConnection) -> AppResult<Repository> {
-        let user = users::table
-            .filter(users::dsl::id.eq(self.user_id))
-            .get_result::<User>(conn)?;
-        let repo = Repository::init(format!("{}/{}", user.name, self.name))?;
+    pub fn init_repository(&self, conn: &PgConnection, hook_bin: &Path) -> AppResult<Repository> {
+        let namespace = self.namespace(conn)?;
+        let repo = Repository::init(format!("{}/{}", namespace, self.name), hook_bin)?;
         Ok(repo)
     }
+
+    /// Installs the hooks into the repositories of all projects, which may be missing (e.g. in the
+    /// repositories created before the hooks were introduced) or point to an old `hook` binary.
+    ///
+    /// Projects whose repository cannot be opened are reported and skipped.
+    pub fn install_all_hooks(conn: &PgConnection, hook_bin: &Path) -> AppResult<()> {
+        let projects = projects::table.load::<Project>(conn)?;
+        for project in projects {
+            let installed = project.open_repository(conn).and_then(
+                |repo| repo.install_hooks(hook_bin),
+            );
+            if let Err(err) = installed {
+                let _ = writeln!(
+                    &mut io::stderr(),
+                    "failed to install the hooks of the project {}: {}",
+                    project.id,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 
 #[derive(Clone, Deserialize)]
 pub struct NewProject {
     pub user: String,
+    /// The name of the organization which owns the project, or `None` for a personal project.
+    pub organization: Option<String>,
     pub name: String,
     pub description: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
 }
 
 impl NewProject {
-    pub fn insert(&self, conn: &PgConnection) -> AppResult<Project> {
+    /// Inserts the project, and initializes its repository whose hooks call the `hook` binary at
+    /// `hook_bin`.
+    pub fn insert(&self, conn: &PgConnection, hook_bin: &Path) -> AppResult<Project> {
         use diesel::types::{Int4, Timestamp, Text, Nullable};
         use diesel::expression::dsl::sql;
 
-        let query = sql::<(Int4, Timestamp, Int4, Text, Nullable<Text>)>(&format!(
-            "INSERT INTO projects (user_id, name, description)
-             SELECT id, {}, {} FROM users
+        let query = sql::<(Int4, Timestamp, Int4, Text, Nullable<Text>, Text, Nullable<Int4>)>(&format!(
+            "INSERT INTO projects (user_id, name, description, visibility, organization_id)
+             SELECT id, {}, {}, {}, {} FROM users
              WHERE users.name = {} LIMIT 1
              RETURNING *",
             escape_str(&self.name),
             self.description.as_ref().map(|s| escape_str(&s)).unwrap_or("NULL".to_owned()),
+            escape_str(self.visibility.as_str()),
+            self.organization
+                .as_ref()
+                .map(|s| format!("(SELECT id FROM organizations WHERE name = {})", escape_str(&s)))
+                .unwrap_or("NULL".to_owned()),
             escape_str(&self.user),
         ));
 
         let project: Project = query.get_result(&*conn)?;
-        project.init_repository(&*conn)?;
+        project.init_repository(&*conn, hook_bin)?;
         Ok(project)
     }
 }
 
 
+fn max_role(a: Option<Role>, b: Option<Role>) -> Option<Role> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a > b { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+
 
 fn escape_str(s: &str) -> String {
     format!("'{}'", s.replace("'", "''"))
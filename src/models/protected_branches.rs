@@ -0,0 +1,138 @@
+use chrono::NaiveDateTime;
+use diesel::{insert, update, delete};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+use error::AppResult;
+use schema::protected_branches;
+use super::projects::Project;
+use super::project_members::Role;
+
+
+#[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
+#[belongs_to(Project)]
+#[table_name = "protected_branches"]
+pub struct ProtectedBranch {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub project_id: i32,
+    pub pattern: String,
+    pub push_role: String,
+    pub allow_force_push: bool,
+    pub allow_deletion: bool,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "protected_branches"]
+pub struct NewProtectedBranch {
+    pub project_id: i32,
+    pub pattern: String,
+    pub push_role: String,
+    pub allow_force_push: bool,
+    pub allow_deletion: bool,
+}
+
+/// Partial update of a protected branch. Fields set to `None` are left unchanged.
+#[derive(Clone, Debug, Default, AsChangeset)]
+#[table_name = "protected_branches"]
+pub struct ProtectedBranchChangeset {
+    pub push_role: Option<String>,
+    pub allow_force_push: Option<bool>,
+    pub allow_deletion: Option<bool>,
+}
+
+impl NewProtectedBranch {
+    pub fn insert(&self, conn: &PgConnection) -> AppResult<ProtectedBranch> {
+        insert(self)
+            .into(protected_branches::table)
+            .get_result::<ProtectedBranch>(&*conn)
+            .map_err(Into::into)
+    }
+}
+
+impl ProtectedBranch {
+    pub fn load_by_project(conn: &PgConnection, project_id: i32) -> AppResult<Vec<Self>> {
+        protected_branches::table
+            .filter(protected_branches::dsl::project_id.eq(project_id))
+            .order(protected_branches::dsl::id)
+            .load::<ProtectedBranch>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find(conn: &PgConnection, project_id: i32, id: i32) -> AppResult<Option<Self>> {
+        protected_branches::table
+            .filter(protected_branches::dsl::project_id.eq(project_id))
+            .filter(protected_branches::dsl::id.eq(id))
+            .get_result::<ProtectedBranch>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Finds the rule which applies to the branch.
+    ///
+    /// An exact match takes precedence over wildcard patterns.
+    pub fn find_matching(conn: &PgConnection, project_id: i32, branch: &str) -> AppResult<Option<Self>> {
+        let mut rules: Vec<_> = Self::load_by_project(conn, project_id)?
+            .into_iter()
+            .filter(|rule| rule.matches(branch))
+            .collect();
+        rules.sort_by_key(|rule| rule.pattern != branch);
+        Ok(rules.into_iter().next())
+    }
+
+    pub fn update(&self, conn: &PgConnection, changeset: &ProtectedBranchChangeset) -> AppResult<Self> {
+        update(protected_branches::table.filter(
+            protected_branches::dsl::id.eq(self.id),
+        )).set(changeset)
+            .get_result::<ProtectedBranch>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn remove(&self, conn: &PgConnection) -> AppResult<()> {
+        delete(protected_branches::table.filter(
+            protected_branches::dsl::id.eq(self.id),
+        )).execute(&*conn)?;
+        Ok(())
+    }
+
+    pub fn push_role(&self) -> Role {
+        Role::from_str(&self.push_role).unwrap_or(Role::Owner)
+    }
+
+    /// Returns whether the branch name matches the pattern, in which `*` matches any sequence of characters.
+    pub fn matches(&self, branch: &str) -> bool {
+        glob_match(self.pattern.as_bytes(), branch.as_bytes())
+    }
+
+    /// Checks whether an update of the branch is allowed, and returns the reason if it is rejected.
+    pub fn check_update(&self, branch: &str, role: Option<Role>, is_deletion: bool, is_force: bool) -> Result<(), String> {
+        if role < Some(self.push_role()) {
+            return Err(format!(
+                "You are not allowed to push to the protected branch '{}'",
+                branch
+            ));
+        }
+        if is_deletion && !self.allow_deletion {
+            return Err(format!(
+                "You are not allowed to delete the protected branch '{}'",
+                branch
+            ));
+        }
+        if is_force && !self.allow_force_push {
+            return Err(format!(
+                "You are not allowed to force push to the protected branch '{}'",
+                branch
+            ));
+        }
+        Ok(())
+    }
+}
+
+
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((&b'*', rest)) => (0..s.len() + 1).any(|i| glob_match(rest, &s[i..])),
+        Some((c, rest)) => s.split_first().map_or(false, |(d, s)| c == d && glob_match(rest, s)),
+    }
+}
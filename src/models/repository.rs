@@ -1,20 +1,168 @@
 use std::fs;
-use std::io::{self, Read};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use chrono::{Datelike, FixedOffset, TimeZone, Timelike};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use git2;
+use tar;
+use zip;
 use serde_json::Value as JsonValue;
 use users::get_user_by_name;
-use error::{AppResult, AppError};
+use crypto;
+use error::{AppResult, AppError, AppErrorKind};
+use rpc::{RpcProcess, Timeouts};
+
+
+/// The names of server-side hooks installed into the repositories.
+const HOOKS: &'static [&'static str] = &["update", "post-receive"];
+
+const MODE_BLOB: i32 = 0o100644;
+const MODE_EXECUTABLE: i32 = 0o100755;
+const MODE_SYMLINK: i32 = 0o120000;
+const MODE_TREE: i32 = 0o040000;
+
+/// The minimum length of abbreviated commit ids accepted as `FileAction::last_commit_id`.
+const MIN_COMMIT_ID_PREFIX: usize = 7;
 
 
 pub struct Repository {
     inner: git2::Repository,
 }
 
+
+/// The identity of an author, committer or tagger of objects created through the API.
+#[derive(Debug)]
+pub struct Signature<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+}
+
+impl<'a> Signature<'a> {
+    fn to_git2(&self) -> AppResult<git2::Signature<'static>> {
+        git2::Signature::now(self.name, self.email).map_err(Into::into)
+    }
+}
+
+
+/// A change of a file applied by `Repository::commit_files`.
+#[derive(Debug)]
+pub struct FileAction {
+    pub path: String,
+    pub kind: FileActionKind,
+    /// The commit which the client has last seen modifying the file.
+    /// The action fails if the file has been modified by another commit since.
+    pub last_commit_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FileActionKind {
+    Create(Vec<u8>),
+    Update(Vec<u8>),
+    Delete,
+    /// Moves the file from `previous_path`, optionally replacing its content.
+    Move {
+        previous_path: String,
+        content: Option<Vec<u8>>,
+    },
+    /// Sets or clears the executable bit.
+    Chmod(bool),
+}
+
+
+/// A file in the tree of a commit, returned by `Repository::find_file`.
+#[derive(Debug)]
+pub struct File {
+    pub path: String,
+    pub blob_id: String,
+    pub mode: String,
+    /// The commit which the revision has been resolved to.
+    pub commit_id: String,
+    /// The size of the content, in bytes.
+    pub size: usize,
+    /// The content, which is omitted if it is larger than the limit given to `Repository::find_file`.
+    pub content: Option<Vec<u8>>,
+}
+
+
+/// The formats of archives written by `Repository::write_archive`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match *self {
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+}
+
+/// A tree to be archived, resolved by `Repository::find_archive_tree`.
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveTree {
+    tree_id: git2::Oid,
+    time: i64,
+}
+
+
+/// Options of diffs returned by `Repository::diff_commit` and `Repository::compare`.
+#[derive(Debug)]
+pub struct DiffOptions {
+    /// The number of context lines around changes.
+    pub context_lines: u32,
+    /// Whether to ignore whitespace when comparing lines.
+    pub ignore_whitespace: bool,
+    /// The maximum total size of hunks, in bytes, which must not exceed `MAX_DIFF_SIZE`.
+    pub max_size: usize,
+}
+
+/// The upper limit of `DiffOptions::max_size` which clients may request.
+pub const MAX_DIFF_SIZE: usize = 8 * 1024 * 1024;
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            context_lines: 3,
+            ignore_whitespace: false,
+            max_size: 512 * 1024,
+        }
+    }
+}
+
+
+/// Conditions to filter the commits listed by `Repository::list_commits`.
+#[derive(Debug, Default)]
+pub struct CommitFilter<'a> {
+    /// Only commits which touch the path.
+    pub path: Option<&'a str>,
+    /// Only commits committed at or after the time, in UNIX timestamp.
+    pub since: Option<i64>,
+    /// Only commits committed at or before the time, in UNIX timestamp.
+    pub until: Option<i64>,
+    /// Only commits whose author name or email contains the string.
+    pub author: Option<&'a str>,
+}
+
 impl Repository {
-    pub(super) fn init<P: AsRef<Path>>(path: P) -> AppResult<Self> {
+    pub(super) fn init<P: AsRef<Path>>(path: P, hook_bin: &Path) -> AppResult<Self> {
         // Get uid/gid
         let user = get_user_by_name("git").unwrap();
         let uid = user.uid();
@@ -46,7 +194,41 @@ impl Repository {
         }
 
         let inner = git2::Repository::open(path)?;
-        Ok(Repository { inner })
+        let repo = Repository { inner };
+        repo.install_hooks(hook_bin)?;
+        Ok(repo)
+    }
+
+    /// Installs the server-side hooks, which call back into the `hook` binary at `hook_bin`.
+    ///
+    /// Hooks which are already up to date are left untouched. Outdated ones are replaced atomically,
+    /// so that a push running concurrently never sees a partially written hook.
+    pub fn install_hooks(&self, hook_bin: &Path) -> AppResult<()> {
+        let hooks_dir = self.inner.path().join("hooks");
+        fs::create_dir_all(&hooks_dir)?;
+        for &name in HOOKS {
+            let script = format!(
+                "#!/bin/sh\nexec {} {} \"$@\"\n",
+                shell_quote(&hook_bin.to_string_lossy()),
+                name
+            );
+            let path = hooks_dir.join(name);
+            if fs::read(&path).ok().map_or(false, |content| content == script.as_bytes()) {
+                continue;
+            }
+
+            let tmp_path = hooks_dir.join(format!("{}.{}.tmp", name, crypto::generate_sha1_random()));
+            let mut f = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o755)
+                .open(&tmp_path)?;
+            f.write_all(script.as_bytes())?;
+            drop(f);
+            fs::rename(&tmp_path, &path)?;
+        }
+        Ok(())
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> AppResult<Self> {
@@ -62,22 +244,21 @@ impl Repository {
         self.inner.path()
     }
 
-    pub fn list_tree(&self, refname: &str, path: Option<&str>, is_recursive: bool) -> AppResult<Vec<JsonValue>> {
-        let path = path.map(|s| PathBuf::from(s));
+    /// Lists the entries of the tree at the path in the revision.
+    ///
+    /// The tree of an empty repository, whose `HEAD` is unborn, is listed as empty.
+    pub fn list_tree(&self, revision: &str, path: Option<&str>, is_recursive: bool) -> AppResult<Vec<JsonValue>> {
+        if revision == "HEAD" && self.inner.is_empty()? {
+            return Ok(Vec::new());
+        }
 
-        let reference = self.inner.find_reference(refname)?.resolve()?;
-        let target = reference.target().ok_or_else(|| {
-            AppError::from("failed to get target object")
-        })?;
-        let commit = self.inner.find_commit(target)?;
+        let path = path.map(|s| s.trim_matches('/'))
+            .and_then(|s| if s.is_empty() { None } else { Some(PathBuf::from(s)) });
 
-        let mut tree = commit.tree()?;
+        let mut tree = self.resolve_tree(revision)?;
         if let Some(ref path) = path {
-            tree = tree.get_path(path)?
-                .to_object(&self.inner)
-                .ok()
-                .and_then(|o| o.into_tree().ok())
-                .unwrap();
+            let entry = find_entry(&tree, &path.to_string_lossy(), git2::ObjectType::Tree)?;
+            tree = self.inner.find_tree(entry.id())?;
         }
 
         let mut objects = Vec::new();
@@ -92,46 +273,1013 @@ impl Repository {
         Ok(objects)
     }
 
-    pub fn get_blob_content(&self, sha: &str) -> AppResult<Option<String>> {
-        let oid = git2::Oid::from_str(sha)?;
-        let blob = match self.inner.find_blob(oid) {
-            Ok(blob) => blob,
-            Err(ref err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
+    /// Returns the id and the content of the blob, which may be given by an abbreviated object name.
+    pub fn get_blob_content(&self, sha: &str) -> AppResult<(String, Vec<u8>)> {
+        let object = self.resolve_object(sha)?;
+        match object.as_blob() {
+            Some(blob) => Ok((blob.id().to_string(), blob.content().to_vec())),
+            None => bail!(AppErrorKind::NotFound(format!("The blob does not exist: {}", sha))),
+        }
+    }
+
+    /// Lists the commits reachable from the revision, newest first.
+    ///
+    /// `offset` and `limit` are applied after filtering.
+    pub fn list_commits(
+        &self,
+        revision: &str,
+        filter: &CommitFilter,
+        offset: usize,
+        limit: usize,
+    ) -> AppResult<Vec<JsonValue>> {
+        let target = self.resolve_commit(revision)?;
+
+        let mut revwalk = self.inner.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME);
+        revwalk.push(target.id())?;
+
+        let mut commits = Vec::new();
+        let mut skipped = 0;
+        for oid in revwalk {
+            let commit = self.inner.find_commit(oid?)?;
+            if !self.matches_filter(&commit, filter)? {
+                continue;
+            }
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+            commits.push(commit_to_json(&commit));
+            if commits.len() >= limit {
+                break;
+            }
+        }
+        Ok(commits)
+    }
+
+    fn matches_filter(&self, commit: &git2::Commit, filter: &CommitFilter) -> AppResult<bool> {
+        let time = commit.time().seconds();
+        if filter.since.map_or(false, |since| time < since) || filter.until.map_or(false, |until| time > until) {
+            return Ok(false);
+        }
+
+        if let Some(author) = filter.author {
+            let signature = commit.author();
+            let matched = signature.name().map_or(false, |name| name.contains(author)) ||
+                signature.email().map_or(false, |email| email.contains(author));
+            if !matched {
+                return Ok(false);
+            }
+        }
+
+        // A commit touches the path if the entry differs from all of its parents. Like the history
+        // simplification of git, a merge which takes the entry from one of the parents as is does
+        // not touch the path, since the change belongs to the commits merged from that parent.
+        if let Some(path) = filter.path {
+            let path = Path::new(path);
+            let entry_id = |tree: &git2::Tree| tree.get_path(path).ok().map(|entry| entry.id());
+            let id = entry_id(&commit.tree()?);
+            if commit.parents().len() == 0 {
+                return Ok(id.is_some());
+            }
+            for parent in commit.parents() {
+                if entry_id(&parent.tree()?) == id {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+
+        Ok(true)
+    }
+
+    /// Resolves the revision into an object.
+    ///
+    /// The revision may be a branch or tag name, a full or abbreviated object name, or any expression
+    /// in the revision syntax of git (e.g. `HEAD~2`, `v1.0^{tree}`). Missing revisions, including an
+    /// unborn `HEAD`, are reported as `AppErrorKind::NotFound`.
+    fn resolve_object(&self, revision: &str) -> AppResult<git2::Object> {
+        if revision.is_empty() {
+            bail!(AppErrorKind::Invalid("The revision is empty".to_owned()));
+        }
+        match self.inner.revparse_single(revision) {
+            Ok(object) => Ok(object),
+            Err(ref err) if err.code() == git2::ErrorCode::NotFound || err.code() == git2::ErrorCode::UnbornBranch => {
+                bail!(AppErrorKind::NotFound(format!("The revision does not exist: {}", revision)))
+            }
+            Err(ref err) if err.code() == git2::ErrorCode::Ambiguous => {
+                bail!(AppErrorKind::Invalid(format!("The revision is ambiguous: {}", revision)))
+            }
+            Err(ref err) if err.code() == git2::ErrorCode::InvalidSpec => {
+                bail!(AppErrorKind::Invalid(format!("Invalid revision: {}", revision)))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Resolves the revision into a commit, peeling annotated tags.
+    fn resolve_commit(&self, revision: &str) -> AppResult<git2::Commit> {
+        let object = self.resolve_object(revision)?;
+        match object.peel(git2::ObjectType::Commit).ok().and_then(|o| o.into_commit().ok()) {
+            Some(commit) => Ok(commit),
+            None => bail!(AppErrorKind::Invalid(format!("The revision does not point to a commit: {}", revision))),
+        }
+    }
+
+    /// Resolves the revision into a tree, peeling annotated tags and commits.
+    fn resolve_tree(&self, revision: &str) -> AppResult<git2::Tree> {
+        let object = self.resolve_object(revision)?;
+        match object.peel(git2::ObjectType::Tree).ok().and_then(|o| o.into_tree().ok()) {
+            Some(tree) => Ok(tree),
+            None => bail!(AppErrorKind::Invalid(format!("The revision does not point to a tree: {}", revision))),
+        }
+    }
+
+    /// Resolves the revision into the object name of a commit.
+    pub fn resolve_commit_id(&self, revision: &str) -> AppResult<String> {
+        self.resolve_commit(revision).map(|commit| commit.id().to_string())
+    }
+
+    /// Returns the commit together with the statistics of changes from its first parent.
+    pub fn get_commit(&self, sha: &str) -> AppResult<JsonValue> {
+        let commit = self.resolve_commit(sha)?;
+
+        let tree = commit.tree()?;
+        let parent_tree = first_parent_tree(&commit)?;
+        let diff = self.inner.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&tree),
+            None,
+        )?;
+        let stats = diff.stats()?;
+
+        let mut item = commit_to_json(&commit);
+        item["stats"] = json!({
+            "additions": stats.insertions(),
+            "deletions": stats.deletions(),
+            "total": stats.insertions() + stats.deletions(),
+            "files_changed": stats.files_changed(),
+        });
+        Ok(item)
+    }
+
+    /// Returns the changes introduced by the commit, compared with its first parent.
+    pub fn diff_commit(&self, sha: &str, options: &DiffOptions) -> AppResult<JsonValue> {
+        let commit = self.resolve_commit(sha)?;
+        let parent_tree = first_parent_tree(&commit)?;
+        self.diff_trees(parent_tree.as_ref(), &commit.tree()?, options)
+    }
+
+    /// Returns the changes between the trees of two revisions.
+    pub fn compare(&self, from: &str, to: &str, options: &DiffOptions) -> AppResult<JsonValue> {
+        let from = self.resolve_commit(from)?;
+        let to = self.resolve_commit(to)?;
+        let mut diff = self.diff_trees(Some(&from.tree()?), &to.tree()?, options)?;
+        diff["from"] = json!(from.id().to_string());
+        diff["to"] = json!(to.id().to_string());
+        Ok(diff)
+    }
+
+    fn diff_trees(&self, old_tree: Option<&git2::Tree>, new_tree: &git2::Tree, options: &DiffOptions) -> AppResult<JsonValue> {
+        let max_size = options.max_size.min(MAX_DIFF_SIZE);
+        let mut opts = git2::DiffOptions::new();
+        opts.context_lines(options.context_lines);
+        opts.ignore_whitespace(options.ignore_whitespace);
+        // Larger files never fit in the limit, so they are treated as binary without being loaded.
+        // Zero means the default of libgit2, hence at least one byte.
+        opts.max_size(max_size.max(1) as i64);
+        let mut diff = self.inner.diff_tree_to_tree(
+            old_tree,
+            Some(new_tree),
+            Some(&mut opts),
+        )?;
+        diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
+
+        // Once the total size of the hunks exceeds the limit, the remaining files are listed without
+        // generating their patches, and their line stats are unknown.
+        let mut size = 0;
+        let mut truncated = false;
+        let mut files = Vec::new();
+        for (idx, delta) in diff.deltas().enumerate() {
+            let old_path = delta.old_file().path().map(|p| p.to_string_lossy().into_owned());
+            let new_path = delta.new_file().path().map(|p| p.to_string_lossy().into_owned());
+            let old_mode = match (old_tree, old_path.as_ref()) {
+                (Some(tree), Some(path)) => tree_entry_mode(tree, path),
+                _ => None,
+            };
+            let new_mode = new_path.as_ref().and_then(|path| tree_entry_mode(new_tree, path));
+
+            let (status, similarity) = match delta.status() {
+                git2::Delta::Added => ("added", None),
+                git2::Delta::Deleted => ("deleted", None),
+                git2::Delta::Renamed => (
+                    "renamed",
+                    Some(self.similarity(delta.old_file().id(), delta.new_file().id())?),
+                ),
+                git2::Delta::Copied => ("copied", None),
+                git2::Delta::Typechange => ("typechange", None),
+                _ => ("modified", None),
+            };
+
+            let (mut additions, mut deletions) = (None, None);
+            let mut hunks = Vec::new();
+            let mut file_truncated = truncated;
+            let patch = if truncated {
+                None
+            } else {
+                git2::Patch::from_diff(&diff, idx)?
+            };
+            if let Some(patch) = patch {
+                let too_large = {
+                    let delta = patch.delta();
+                    delta.old_file().size().max(delta.new_file().size()) > max_size as u64
+                };
+                let (_, a, d) = patch.line_stats()?;
+                if !too_large {
+                    additions = Some(a);
+                    deletions = Some(d);
+                }
+
+                let patch_size = patch.size(true, true, false);
+                if too_large {
+                    file_truncated = true;
+                } else if size + patch_size > max_size {
+                    truncated = true;
+                    file_truncated = true;
+                } else {
+                    size += patch_size;
+                    for h in 0..patch.num_hunks() {
+                        hunks.push(hunk_to_json(&patch, h)?);
+                    }
+                }
+            }
+
+            files.push(json!({
+                "status": status,
+                "similarity": similarity,
+                "old_path": old_path,
+                "new_path": new_path,
+                "old_mode": old_mode,
+                "new_mode": new_mode,
+                "additions": additions,
+                "deletions": deletions,
+                "truncated": file_truncated,
+                "hunks": hunks,
+            }));
+        }
+
+        Ok(json!({
+            "files": files,
+            "truncated": truncated,
+        }))
+    }
+
+    /// Returns the similarity of two blobs in percentage, which is estimated from the number of unchanged lines.
+    fn similarity(&self, old_id: git2::Oid, new_id: git2::Oid) -> AppResult<usize> {
+        if old_id == new_id {
+            return Ok(100);
+        }
+        let old_blob = self.inner.find_blob(old_id)?;
+        let new_blob = self.inner.find_blob(new_id)?;
+        let patch = git2::Patch::from_blobs(&old_blob, None, &new_blob, None, None)?;
+        let (_, _, deletions) = patch.line_stats()?;
+
+        let old_lines = count_lines(old_blob.content());
+        let new_lines = count_lines(new_blob.content());
+        if old_lines + new_lines == 0 {
+            return Ok(100);
+        }
+        let unchanged = old_lines.saturating_sub(deletions);
+        Ok(unchanged * 200 / (old_lines + new_lines))
+    }
+
+    /// Returns the name of the default branch, which is the target of `HEAD`.
+    ///
+    /// The branch may not exist yet if the repository is empty.
+    pub fn default_branch(&self) -> AppResult<Option<String>> {
+        let head = self.inner.find_reference("HEAD")?;
+        Ok(head.symbolic_target().and_then(|target| {
+            if target.starts_with("refs/heads/") {
+                Some(target["refs/heads/".len()..].to_owned())
+            } else {
+                None
+            }
+        }))
+    }
+
+    /// Points `HEAD` to the branch, which must exist.
+    pub fn set_default_branch(&self, name: &str) -> AppResult<()> {
+        let refname = format!("refs/heads/{}", name);
+        self.inner.find_reference(&refname)?;
+        self.inner.set_head(&refname)?;
+        Ok(())
+    }
+
+    pub fn list_branches(&self) -> AppResult<Vec<JsonValue>> {
+        let default_branch = self.default_branch()?;
+        let mut branches = Vec::new();
+        for reference in self.inner.references_glob("refs/heads/*")? {
+            let reference = reference?;
+            if let Some(branch) = self.branch_to_json(&reference, default_branch.as_ref())? {
+                branches.push(branch);
+            }
+        }
+        Ok(branches)
+    }
+
+    pub fn get_branch(&self, name: &str) -> AppResult<Option<JsonValue>> {
+        let reference = match self.find_reference(&format!("refs/heads/{}", name))? {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+        self.branch_to_json(&reference, self.default_branch()?.as_ref())
+    }
+
+    /// Creates a branch which points to the revision.
+    pub fn create_branch(&self, name: &str, revision: &str) -> AppResult<JsonValue> {
+        let refname = format!("refs/heads/{}", name);
+        if !git2::Reference::is_valid_name(&refname) {
+            bail!(AppErrorKind::Invalid(format!("Invalid branch name: {}", name)));
+        }
+        let commit = self.resolve_commit(revision)?;
+        self.inner.branch(name, &commit, false)?;
+        self.get_branch(name)?.ok_or_else(|| {
+            AppError::from("failed to find the created branch")
+        })
+    }
+
+    /// Deletes the branch, and returns the commit it pointed to.
+    pub fn delete_branch(&self, name: &str) -> AppResult<Option<String>> {
+        self.delete_reference(&format!("refs/heads/{}", name))
+    }
+
+    pub fn list_tags(&self) -> AppResult<Vec<JsonValue>> {
+        let mut tags = Vec::new();
+        for reference in self.inner.references_glob("refs/tags/*")? {
+            tags.push(self.tag_to_json(&reference?)?);
+        }
+        Ok(tags)
+    }
+
+    pub fn get_tag(&self, name: &str) -> AppResult<Option<JsonValue>> {
+        match self.find_reference(&format!("refs/tags/{}", name))? {
+            Some(reference) => self.tag_to_json(&reference).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates a tag which points to the revision.
+    ///
+    /// The tag is annotated if `message` is given, and is lightweight otherwise.
+    pub fn create_tag(
+        &self,
+        name: &str,
+        revision: &str,
+        message: Option<&str>,
+        tagger: &Signature,
+    ) -> AppResult<JsonValue> {
+        let refname = format!("refs/tags/{}", name);
+        if !git2::Reference::is_valid_name(&refname) {
+            bail!(AppErrorKind::Invalid(format!("Invalid tag name: {}", name)));
+        }
+        let commit = self.resolve_commit(revision)?;
+        match message {
+            Some(message) => {
+                let tagger = tagger.to_git2()?;
+                self.inner.tag(name, commit.as_object(), &tagger, message, false)?;
+            }
+            None => {
+                self.inner.reference(&refname, commit.id(), false, "tag: created")?;
+            }
+        }
+        self.get_tag(name)?.ok_or_else(
+            || AppError::from("failed to find the created tag"),
+        )
+    }
+
+    /// Deletes the tag, and returns the object it pointed to.
+    pub fn delete_tag(&self, name: &str) -> AppResult<Option<String>> {
+        self.delete_reference(&format!("refs/tags/{}", name))
+    }
+
+    fn find_reference(&self, refname: &str) -> AppResult<Option<git2::Reference>> {
+        match self.inner.find_reference(refname) {
+            Ok(reference) => Ok(Some(reference)),
+            Err(ref err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn delete_reference(&self, refname: &str) -> AppResult<Option<String>> {
+        let mut reference = match self.find_reference(refname)? {
+            Some(reference) => reference,
+            None => return Ok(None),
+        };
+        let target = reference.target().map(|oid| oid.to_string());
+        reference.delete()?;
+        Ok(target)
+    }
+
+    fn branch_to_json(&self, reference: &git2::Reference, default_branch: Option<&String>) -> AppResult<Option<JsonValue>> {
+        let name = match reference.shorthand() {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let commit = match reference.target() {
+            Some(target) => self.inner.find_commit(target)?,
+            None => return Ok(None),
+        };
+        Ok(Some(json!({
+            "name": name,
+            "commit": commit_to_json(&commit),
+            "default": default_branch.map_or(false, |branch| branch == name),
+        })))
+    }
+
+    fn tag_to_json(&self, reference: &git2::Reference) -> AppResult<JsonValue> {
+        let target = reference.target().ok_or_else(
+            || AppError::from("failed to get target object"),
+        )?;
+        let commit = self.inner
+            .find_object(target, None)?
+            .peel(git2::ObjectType::Commit)?
+            .into_commit()
+            .map_err(|_| AppError::from("failed to peel to a commit"))?;
+
+        let (message, tagger) = match self.inner.find_tag(target) {
+            Ok(tag) => (
+                tag.message().map(|s| s.to_owned()),
+                tag.tagger().map(|tagger| signature_to_json(&tagger)),
+            ),
+            Err(_) => (None, None),
+        };
+
+        Ok(json!({
+            "name": reference.shorthand(),
+            "target": target.to_string(),
+            "message": message,
+            "tagger": tagger,
+            "commit": commit_to_json(&commit),
+        }))
+    }
+
+    /// Creates a commit on the branch, which applies the actions to the tree of the current head.
+    ///
+    /// A missing branch is created from the revision `start` if given. Otherwise, it is created with
+    /// a root commit, which is only allowed while the repository is empty.
+    /// Either all actions are applied or, if any of them fails, nothing is changed.
+    ///
+    /// Returns the object names of the previous head (if any) and the new commit.
+    pub fn commit_files(
+        &self,
+        branch: &str,
+        start: Option<&str>,
+        actions: &[FileAction],
+        message: &str,
+        author: &Signature,
+    ) -> AppResult<(Option<String>, String)> {
+        let refname = format!("refs/heads/{}", branch);
+        if !git2::Reference::is_valid_name(&refname) {
+            bail!(AppErrorKind::Invalid(format!("Invalid branch name: {}", branch)));
+        }
+
+        let is_empty = self.inner.references_glob("refs/heads/*")?.next().is_none();
+        let head = match self.find_reference(&refname)?.and_then(|r| r.target()) {
+            Some(target) => Some(self.inner.find_commit(target)?),
+            None => None,
+        };
+        let parent = match (head, start) {
+            (Some(_), Some(_)) => bail!(AppErrorKind::Invalid(format!("The branch already exists: {}", branch))),
+            (Some(head), None) => Some(head),
+            (None, Some(start)) => Some(self.resolve_commit(start)?),
+            (None, None) if is_empty => None,
+            (None, None) => bail!(AppErrorKind::Invalid(format!("The branch does not exist: {}", branch))),
+        };
+        let old = if start.is_some() {
+            None
+        } else {
+            parent.as_ref().map(|parent| parent.id().to_string())
+        };
+
+        // Actions are applied to trees written into the object database, which is not visible
+        // from any reference until the commit is created.
+        let mut tree_id = parent.as_ref().map(|parent| parent.tree_id());
+        for action in actions {
+            if let (Some(last_commit_id), Some(parent)) = (action.last_commit_id.as_ref(), parent.as_ref()) {
+                let path = match action.kind {
+                    FileActionKind::Move { ref previous_path, .. } => previous_path,
+                    _ => &action.path,
+                };
+                if !is_commit_id_prefix(last_commit_id) {
+                    bail!(AppErrorKind::Invalid(format!("Invalid last commit id: {}", last_commit_id)));
+                }
+                let latest = self.last_commit_for_path(parent.id(), path)?;
+                let prefix = last_commit_id.to_lowercase();
+                if !latest.map_or(false, |id| id.to_string().starts_with(&prefix)) {
+                    bail!(AppErrorKind::Conflict(format!(
+                        "The file has been modified since {}: {}",
+                        last_commit_id,
+                        path
+                    )));
+                }
+            }
+            tree_id = self.apply_action(tree_id, action)?;
+        }
+
+        let tree_id = match tree_id {
+            Some(tree_id) => tree_id,
+            None => self.inner.treebuilder(None)?.write()?,
+        };
+        if parent.as_ref().map(|parent| parent.tree_id()) == Some(tree_id) {
+            bail!(AppErrorKind::Invalid("The commit makes no changes".to_owned()));
+        }
+        let tree = self.inner.find_tree(tree_id)?;
+
+        // The reference is updated only if it still points to the parent.
+        let signature = author.to_git2()?;
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let oid = match self.inner.commit(Some(&refname), &signature, &signature, message, &tree, &parents) {
+            Ok(oid) => oid,
+            Err(ref err) if err.code() == git2::ErrorCode::Modified => {
+                bail!(AppErrorKind::Conflict(format!("The branch has been updated concurrently: {}", branch)))
+            }
             Err(err) => return Err(err.into()),
         };
-        Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+
+        if is_empty {
+            self.inner.set_head(&refname)?;
+        }
+
+        Ok((old, oid.to_string()))
     }
 
-    pub fn run_rpc_command<'a>(&self, service: &str, stdin: Option<&mut Box<Read + 'a>>) -> AppResult<Vec<u8>> {
-        let args: Vec<&str> = if stdin.is_some() {
-            vec![service, "--stateless-rpc", "."]
+    fn last_commit_for_path(&self, start: git2::Oid, path: &str) -> AppResult<Option<git2::Oid>> {
+        let filter = CommitFilter {
+            path: Some(path),
+            ..CommitFilter::default()
+        };
+        let mut revwalk = self.inner.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME);
+        revwalk.push(start)?;
+        for oid in revwalk {
+            let commit = self.inner.find_commit(oid?)?;
+            if self.matches_filter(&commit, &filter)? {
+                return Ok(Some(commit.id()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Applies the action to the tree, and returns the resulting tree, or `None` if it becomes empty.
+    fn apply_action(&self, tree_id: Option<git2::Oid>, action: &FileAction) -> AppResult<Option<git2::Oid>> {
+        let tree = match tree_id {
+            Some(tree_id) => Some(self.inner.find_tree(tree_id)?),
+            None => None,
+        };
+        let components = split_path(&action.path)?;
+        let existing = tree.as_ref().and_then(|tree| {
+            tree.get_path(Path::new(&action.path)).ok().map(|entry| {
+                (entry.kind(), entry.filemode())
+            })
+        });
+
+        match action.kind {
+            FileActionKind::Create(ref content) => {
+                if existing.is_some() {
+                    bail!(AppErrorKind::Conflict(format!("The file already exists: {}", action.path)));
+                }
+                let blob = self.inner.blob(content)?;
+                self.edit_tree(tree.as_ref(), &components, Some((blob, MODE_BLOB)))
+            }
+            FileActionKind::Update(ref content) => {
+                let mode = match existing {
+                    Some((Some(git2::ObjectType::Blob), mode)) => mode,
+                    _ => bail!(AppErrorKind::Conflict(format!("The file does not exist: {}", action.path))),
+                };
+                let blob = self.inner.blob(content)?;
+                self.edit_tree(tree.as_ref(), &components, Some((blob, mode)))
+            }
+            FileActionKind::Delete => {
+                match existing {
+                    Some((Some(git2::ObjectType::Blob), _)) => (),
+                    _ => bail!(AppErrorKind::Conflict(format!("The file does not exist: {}", action.path))),
+                }
+                self.edit_tree(tree.as_ref(), &components, None)
+            }
+            FileActionKind::Move {
+                ref previous_path,
+                ref content,
+            } => {
+                if existing.is_some() {
+                    bail!(AppErrorKind::Conflict(format!("The file already exists: {}", action.path)));
+                }
+                let previous = tree.as_ref().and_then(|tree| {
+                    tree.get_path(Path::new(previous_path)).ok().map(|entry| {
+                        (entry.kind(), entry.id(), entry.filemode())
+                    })
+                });
+                let (id, mode) = match previous {
+                    Some((Some(git2::ObjectType::Blob), id, mode)) => (id, mode),
+                    _ => bail!(AppErrorKind::Conflict(format!("The file does not exist: {}", previous_path))),
+                };
+                let id = match *content {
+                    Some(ref content) => self.inner.blob(content)?,
+                    None => id,
+                };
+                let tree_id = self.edit_tree(tree.as_ref(), &split_path(previous_path)?, None)?;
+                let tree = match tree_id {
+                    Some(tree_id) => Some(self.inner.find_tree(tree_id)?),
+                    None => None,
+                };
+                self.edit_tree(tree.as_ref(), &components, Some((id, mode)))
+            }
+            FileActionKind::Chmod(executable) => {
+                let id = match tree.as_ref().and_then(|tree| tree.get_path(Path::new(&action.path)).ok()) {
+                    Some(ref entry) if entry.kind() == Some(git2::ObjectType::Blob) => entry.id(),
+                    _ => bail!(AppErrorKind::Conflict(format!("The file does not exist: {}", action.path))),
+                };
+                let mode = if executable { MODE_EXECUTABLE } else { MODE_BLOB };
+                self.edit_tree(tree.as_ref(), &components, Some((id, mode)))
+            }
+        }
+    }
+
+    /// Inserts (or removes, if `entry` is `None`) the entry at the path, and writes the modified trees.
+    ///
+    /// Returns `None` if the tree becomes empty, so that empty directories are removed.
+    fn edit_tree(
+        &self,
+        base: Option<&git2::Tree>,
+        components: &[&str],
+        entry: Option<(git2::Oid, i32)>,
+    ) -> AppResult<Option<git2::Oid>> {
+        let (&name, rest) = components.split_first().ok_or_else(
+            || AppError::from("empty path"),
+        )?;
+        let mut builder = self.inner.treebuilder(base)?;
+        let current = base.and_then(|tree| tree.get_name(name).map(|e| (e.id(), e.kind())));
+
+        if rest.is_empty() {
+            match entry {
+                Some((oid, mode)) => {
+                    builder.insert(name, oid, mode)?;
+                }
+                None => builder.remove(name)?,
+            }
         } else {
-            vec![service, "--stateless-rpc", "--advertise-refs", "."]
+            let subtree = match current {
+                Some((id, Some(git2::ObjectType::Tree))) => Some(self.inner.find_tree(id)?),
+                Some(_) => bail!(AppErrorKind::Conflict(format!("A file exists in place of the directory: {}", name))),
+                None => None,
+            };
+            match self.edit_tree(subtree.as_ref(), rest, entry)? {
+                Some(oid) => {
+                    builder.insert(name, oid, MODE_TREE)?;
+                }
+                None => {
+                    if current.is_some() {
+                        builder.remove(name)?;
+                    }
+                }
+            }
+        }
+
+        if builder.len() == 0 {
+            Ok(None)
+        } else {
+            builder.write().map(Some).map_err(Into::into)
+        }
+    }
+
+    /// Finds the file at the path in the revision.
+    ///
+    /// The content is copied only if it is not larger than `max_size`.
+    pub fn find_file(&self, revision: &str, path: &str, max_size: Option<usize>) -> AppResult<File> {
+        let commit = self.resolve_commit(revision)?;
+        let path = path.trim_matches('/');
+        let entry = find_entry(&commit.tree()?, path, git2::ObjectType::Blob)?;
+        let blob = self.inner.find_blob(entry.id())?;
+        let size = blob.content().len();
+        let content = if max_size.map_or(true, |max_size| size <= max_size) {
+            Some(blob.content().to_vec())
+        } else {
+            None
         };
 
-        let mut child = Command::new("/usr/bin/git")
-            .args(args)
-            .current_dir(self.inner.path())
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?;
+        Ok(File {
+            path: path.to_owned(),
+            blob_id: entry.id().to_string(),
+            mode: format!("{:06o}", entry.filemode()),
+            commit_id: commit.id().to_string(),
+            size: size,
+            content: content,
+        })
+    }
+
+    /// Returns the latest commit which modified the file, walking the history from the commit
+    /// where the file was found.
+    pub fn last_commit_for_file(&self, file: &File) -> AppResult<Option<String>> {
+        let commit_id = git2::Oid::from_str(&file.commit_id)?;
+        let last_commit_id = self.last_commit_for_path(commit_id, &file.path)?;
+        Ok(last_commit_id.map(|id| id.to_string()))
+    }
+
+    /// Returns the blame of the file in the revision, as ranges of lines grouped by the commits which
+    /// last modified them.
+    pub fn blame(&self, revision: &str, path: &str) -> AppResult<Vec<JsonValue>> {
+        let commit = self.resolve_commit(revision)?;
+        let path = path.trim_matches('/');
+        let entry = find_entry(&commit.tree()?, path, git2::ObjectType::Blob)?;
+        let blob = self.inner.find_blob(entry.id())?;
+        let content = String::from_utf8_lossy(blob.content());
+        let lines: Vec<&str> = content.lines().collect();
+
+        let mut opts = git2::BlameOptions::new();
+        opts.newest_commit(commit.id());
+        let blame = self.inner.blame_file(Path::new(path), Some(&mut opts))?;
+
+        // Adjacent hunks from the same commit are merged into a range.
+        let mut ranges: Vec<(git2::Oid, usize, usize)> = Vec::new();
+        for hunk in blame.iter() {
+            let (id, start, count) = (hunk.final_commit_id(), hunk.final_start_line(), hunk.lines_in_hunk());
+            let merged = match ranges.last_mut() {
+                Some(last) if last.0 == id && last.1 + last.2 == start => {
+                    last.2 += count;
+                    true
+                }
+                _ => false,
+            };
+            if !merged {
+                ranges.push((id, start, count));
+            }
+        }
+
+        let mut commits = HashMap::new();
+        let mut items = Vec::with_capacity(ranges.len());
+        for (id, start, count) in ranges {
+            if !commits.contains_key(&id) {
+                let commit = self.inner.find_commit(id)?;
+                commits.insert(id, commit_to_json(&commit));
+            }
+            // Line numbers are 1-based.
+            let range_lines: Vec<&str> = lines.iter().skip(start - 1).take(count).cloned().collect();
+            items.push(json!({
+                "commit": commits[&id],
+                "start_line": start,
+                "end_line": start + count - 1,
+                "lines": range_lines,
+            }));
+        }
+        Ok(items)
+    }
+
+    /// Resolves the tree at the path in the revision, to be passed to `write_archive`.
+    pub fn find_archive_tree(&self, revision: &str, path: Option<&str>) -> AppResult<ArchiveTree> {
+        let commit = self.resolve_commit(revision)?;
+        let tree_id = match path {
+            Some(path) if !path.trim_matches('/').is_empty() => {
+                find_entry(&commit.tree()?, path.trim_matches('/'), git2::ObjectType::Tree)?.id()
+            }
+            _ => commit.tree_id(),
+        };
+        Ok(ArchiveTree {
+            tree_id: tree_id,
+            time: commit.time().seconds(),
+        })
+    }
+
+    /// Writes the archive of the tree, whose entries are placed under `prefix`.
+    ///
+    /// Both formats are written as the tree is walked, so the archive is never held in memory.
+    /// Zip entries are followed by data descriptors in place of seeking back to their headers, and
+    /// symbolic links are stored with the unix mode `0o120777` and the link target as the content.
+    pub fn write_archive<W: Write>(&self, archive: &ArchiveTree, prefix: &str, format: ArchiveFormat, out: W) -> AppResult<()> {
+        let tree = self.inner.find_tree(archive.tree_id)?;
+        match format {
+            ArchiveFormat::Tar => {
+                self.write_tar(&tree, archive.time, prefix, out)?;
+            }
+            ArchiveFormat::TarGz => {
+                let encoder = GzEncoder::new(out, Compression::Default);
+                self.write_tar(&tree, archive.time, prefix, encoder)?.finish()?;
+            }
+            ArchiveFormat::Zip => {
+                self.write_zip(&tree, archive.time, prefix, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_tar<W: Write>(&self, tree: &git2::Tree, time: i64, prefix: &str, out: W) -> AppResult<W> {
+        let mut builder = tar::Builder::new(out);
+        let mut header = tar_header(tar::EntryType::Directory, 0o755, time);
+        builder.append_data(&mut header, prefix, io::empty())?;
+
+        visit_tree(&self.inner, tree, PathBuf::from(prefix), &mut |path, entry| {
+            match (entry.kind(), entry.filemode()) {
+                (Some(git2::ObjectType::Tree), _) => {
+                    let mut header = tar_header(tar::EntryType::Directory, 0o755, time);
+                    builder.append_data(&mut header, path, io::empty())?;
+                }
+                (Some(git2::ObjectType::Blob), MODE_SYMLINK) => {
+                    let blob = self.inner.find_blob(entry.id())?;
+                    let target = String::from_utf8_lossy(blob.content()).into_owned();
+                    let mut header = tar_header(tar::EntryType::Symlink, 0o777, time);
+                    header.set_link_name(&target)?;
+                    builder.append_data(&mut header, path, io::empty())?;
+                }
+                (Some(git2::ObjectType::Blob), mode) => {
+                    let blob = self.inner.find_blob(entry.id())?;
+                    let mode = if mode == MODE_EXECUTABLE { 0o755 } else { 0o644 };
+                    let mut header = tar_header(tar::EntryType::Regular, mode, time);
+                    header.set_size(blob.content().len() as u64);
+                    builder.append_data(&mut header, path, blob.content())?;
+                }
+                // Submodules are not included, as `git archive` does.
+                _ => (),
+            }
+            Ok(())
+        })?;
 
-        if let Some(stdin) = stdin {
-            io::copy(stdin, child.stdin.as_mut().unwrap())?;
+        builder.into_inner().map_err(Into::into)
+    }
+
+    fn write_zip<W: Write>(&self, tree: &git2::Tree, time: i64, prefix: &str, out: W) -> AppResult<W> {
+        let options = zip::write::SimpleFileOptions::default().last_modified_time(zip_time(time));
+        let mut writer = zip::ZipWriter::new_stream(out);
+        writer.add_directory(prefix, options)?;
+
+        visit_tree(&self.inner, tree, PathBuf::from(prefix), &mut |path, entry| {
+            let name = path.to_string_lossy().into_owned();
+            match (entry.kind(), entry.filemode()) {
+                (Some(git2::ObjectType::Tree), _) => {
+                    writer.add_directory(format!("{}/", name), options)?;
+                }
+                (Some(git2::ObjectType::Blob), MODE_SYMLINK) => {
+                    let blob = self.inner.find_blob(entry.id())?;
+                    let target = String::from_utf8_lossy(blob.content()).into_owned();
+                    writer.add_symlink(name, target, options.unix_permissions(0o777))?;
+                }
+                (Some(git2::ObjectType::Blob), mode) => {
+                    let blob = self.inner.find_blob(entry.id())?;
+                    let options = options
+                        .compression_method(zip::CompressionMethod::Deflated)
+                        .unix_permissions(if mode == MODE_EXECUTABLE { 0o755 } else { 0o644 })
+                        .large_file(blob.content().len() as u64 >= zip::ZIP64_BYTES_THR);
+                    writer.start_file(name, options)?;
+                    writer.write_all(blob.content())?;
+                }
+                _ => (),
+            }
+            Ok(())
+        })?;
+
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// Lists the commits introduced by an update of a reference from `old` to `new`, newest first.
+    ///
+    /// For a newly created reference (`old` is `None`), commits reachable from other branches are excluded.
+    pub fn list_pushed_commits(&self, old: Option<&str>, new: &str, limit: usize) -> AppResult<Vec<JsonValue>> {
+        let mut revwalk = self.inner.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME);
+        // Annotated tags are peeled to the commits they point to.
+        let new = self.inner
+            .find_object(git2::Oid::from_str(new)?, None)?
+            .peel(git2::ObjectType::Commit)?
+            .id();
+        revwalk.push(new)?;
+        match old {
+            Some(old) => revwalk.hide(git2::Oid::from_str(old)?)?,
+            None => {
+                for reference in self.inner.references_glob("refs/heads/*")? {
+                    let reference = reference?;
+                    match reference.target() {
+                        Some(target) if target != new => revwalk.hide(target)?,
+                        _ => (),
+                    }
+                }
+            }
         }
 
-        let output = child.wait_with_output()?;
-        if output.status.success() {
-            Ok(output.stdout)
+        let mut commits = Vec::new();
+        for oid in revwalk.take(limit) {
+            let commit = self.inner.find_commit(oid?)?;
+            commits.push(commit_to_json(&commit));
+        }
+        Ok(commits)
+    }
+
+    /// Returns the content of `info/refs` for the dumb HTTP protocol.
+    ///
+    /// Each line is in the form of `<object name>\t<refname>`, and annotated tags are followed by the
+    /// objects they point to, in the same way as `git update-server-info`.
+    pub fn dumb_info_refs(&self) -> AppResult<Vec<u8>> {
+        let mut refs = Vec::new();
+        for reference in self.inner.references()? {
+            let reference = reference?;
+            let (name, target) = match (reference.name(), reference.target()) {
+                (Some(name), Some(target)) => (name.to_owned(), target),
+                _ => continue,
+            };
+            let object = self.inner.find_object(target, None)?;
+            let peeled = if object.kind() == Some(git2::ObjectType::Tag) {
+                // A tag is peeled until the referenced object is no longer a tag.
+                Some(object.peel(git2::ObjectType::Any)?.id())
+            } else {
+                None
+            };
+            refs.push((name, target, peeled));
+        }
+        refs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut body = Vec::new();
+        for (name, target, peeled) in refs {
+            body.extend(format!("{}\t{}\n", target, name).as_bytes());
+            if let Some(peeled) = peeled {
+                body.extend(format!("{}\t{}^{{}}\n", peeled, name).as_bytes());
+            }
+        }
+        Ok(body)
+    }
+
+    /// Returns the content of `HEAD`, which is either a symbolic reference or an object name.
+    pub fn dumb_head(&self) -> AppResult<Vec<u8>> {
+        let head = self.inner.find_reference("HEAD")?;
+        let content = match (head.symbolic_target(), head.target()) {
+            (Some(target), _) => format!("ref: {}\n", target),
+            (None, Some(target)) => format!("{}\n", target),
+            (None, None) => return Err(AppError::from("failed to read HEAD")),
+        };
+        Ok(content.into_bytes())
+    }
+
+    /// Returns the content of `objects/info/packs` for the dumb HTTP protocol.
+    pub fn dumb_info_packs(&self) -> AppResult<Vec<u8>> {
+        let mut packs = Vec::new();
+        let pack_dir = self.inner.path().join("objects/pack");
+        if pack_dir.is_dir() {
+            for entry in fs::read_dir(pack_dir)? {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                if name.starts_with("pack-") && name.ends_with(".pack") {
+                    packs.push(name);
+                }
+            }
+        }
+        packs.sort();
+
+        let mut body = Vec::new();
+        for pack in packs {
+            body.extend(format!("P {}\n", pack).as_bytes());
+        }
+        body.extend(b"\n");
+        Ok(body)
+    }
+
+    /// Returns the path of the file in the object database, which is served by the dumb HTTP protocol.
+    ///
+    /// `path` is relative to the `objects` directory, and must be validated by the caller.
+    pub fn object_file_path(&self, path: &str) -> PathBuf {
+        self.inner.path().join("objects").join(path)
+    }
+
+    /// Spawns `git <service> --stateless-rpc`.
+    ///
+    /// The references are advertised if `advertise_refs` is true, and otherwise the request body has
+    /// to be written through `RpcProcess::write_input`. `envs` are passed to the git process, and are
+    /// visible from the server-side hooks.
+    pub fn run_rpc_command(
+        &self,
+        service: &str,
+        advertise_refs: bool,
+        protocol: Option<&str>,
+        envs: &[(&str, String)],
+        timeouts: Timeouts,
+    ) -> AppResult<RpcProcess> {
+        let args: Vec<&str> = if advertise_refs {
+            vec![service, "--stateless-rpc", "--advertise-refs", "."]
         } else {
-            let message = format!(
-                "`git {}` was exited with non-zero status: {}",
-                service,
-                String::from_utf8_lossy(&output.stderr)
-            );
-            Err(io::Error::new(io::ErrorKind::Other, message).into())
+            vec![service, "--stateless-rpc", "."]
+        };
+
+        let mut command = Command::new("/usr/bin/git");
+        command.args(args);
+        // The protocol version requested by the client (e.g. `version=2`) is passed as `GIT_PROTOCOL`,
+        // which makes git switch the format of both the advertisement and the responses.
+        if let Some(protocol) = protocol {
+            command.env("GIT_PROTOCOL", protocol);
+        }
+        for &(key, ref val) in envs {
+            command.env(key, val);
         }
+        let child = command
+            .current_dir(self.inner.path())
+            .stdin(if advertise_refs { Stdio::null() } else { Stdio::piped() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        Ok(RpcProcess::new(service, child, timeouts))
     }
 }
 
@@ -176,3 +1324,256 @@ fn walk_tree(
     }
     Ok(())
 }
+
+
+/// Finds the entry of the kind at the path in the tree.
+fn find_entry(tree: &git2::Tree, path: &str, kind: git2::ObjectType) -> AppResult<git2::TreeEntry<'static>> {
+    let entry = match tree.get_path(Path::new(path)) {
+        Ok(entry) => entry,
+        Err(ref err) if err.code() == git2::ErrorCode::NotFound => {
+            bail!(AppErrorKind::NotFound(format!("The path does not exist: {}", path)))
+        }
+        Err(err) => return Err(err.into()),
+    };
+    if entry.kind() != Some(kind) {
+        bail!(AppErrorKind::NotFound(format!("The path is not a {}: {}", kind.str(), path)));
+    }
+    Ok(entry)
+}
+
+/// Returns whether the content looks binary, in the same way as git does: it contains a NUL byte
+/// within the first 8000 bytes.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Calls `f` for each entry in the tree recursively, where directories are visited before their contents.
+fn visit_tree(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    path: PathBuf,
+    f: &mut FnMut(&Path, &git2::TreeEntry) -> AppResult<()>,
+) -> AppResult<()> {
+    for entry in tree {
+        let name = entry.name().ok_or_else(
+            || AppError::from("Failed to get entry name"),
+        )?;
+        let path = path.join(&name);
+        f(&path, &entry)?;
+        if entry.kind() == Some(git2::ObjectType::Tree) {
+            let tree = repo.find_tree(entry.id())?;
+            visit_tree(repo, &tree, path, f)?;
+        }
+    }
+    Ok(())
+}
+
+fn tar_header(entry_type: tar::EntryType, mode: u32, time: i64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_mtime(time as u64);
+    header.set_size(0);
+    header
+}
+
+
+/// Converts the UNIX timestamp to the modification time of zip entries, which is in UTC here and
+/// clamped to the range representable in zip archives (1980 to 2107).
+fn zip_time(time: i64) -> zip::DateTime {
+    let time = FixedOffset::east(0).timestamp(time, 0);
+    let (year, month, day) = (time.year(), time.month(), time.day());
+    if year < 1980 {
+        return zip::DateTime::default();
+    }
+    zip::DateTime::from_date_and_time(
+        year as u16,
+        month as u8,
+        day as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    ).unwrap_or_else(|_| zip::DateTime::from_date_and_time(2107, 12, 31, 23, 59, 58).unwrap())
+}
+
+
+fn signature_to_json(signature: &git2::Signature) -> JsonValue {
+    let when = signature.when();
+    json!({
+        "name": signature.name(),
+        "email": signature.email(),
+        "timestamp": format_time(&when),
+    })
+}
+
+fn commit_to_json(commit: &git2::Commit) -> JsonValue {
+    json!({
+        "id": commit.id().to_string(),
+        "short_id": commit.id().to_string()[..7].to_owned(),
+        "title": commit.summary(),
+        "parent_ids": commit.parent_ids().map(|id| id.to_string()).collect::<Vec<_>>(),
+        "message": commit.message(),
+        "author": signature_to_json(&commit.author()),
+        "committer": signature_to_json(&commit.committer()),
+    })
+}
+
+fn format_time(time: &git2::Time) -> String {
+    FixedOffset::east(time.offset_minutes() * 60)
+        .timestamp(time.seconds(), 0)
+        .to_rfc3339()
+}
+
+
+fn first_parent_tree<'a>(commit: &git2::Commit<'a>) -> AppResult<Option<git2::Tree<'a>>> {
+    match commit.parents().next() {
+        Some(parent) => Ok(Some(parent.tree()?)),
+        None => Ok(None),
+    }
+}
+
+/// Returns the file mode of the entry at the path, in the octal form (e.g. `"100644"`).
+fn tree_entry_mode(tree: &git2::Tree, path: &str) -> Option<String> {
+    tree.get_path(Path::new(path)).ok().map(|entry| {
+        format!("{:06o}", entry.filemode())
+    })
+}
+
+fn count_lines(content: &[u8]) -> usize {
+    let lines = content.iter().filter(|&&b| b == b'\n').count();
+    if content.last().map_or(false, |&b| b != b'\n') {
+        lines + 1
+    } else {
+        lines
+    }
+}
+
+fn hunk_to_json(patch: &git2::Patch, idx: usize) -> AppResult<JsonValue> {
+    let (hunk, num_lines) = patch.hunk(idx)?;
+    let mut lines = Vec::with_capacity(num_lines);
+    for l in 0..num_lines {
+        let line = patch.line_in_hunk(idx, l)?;
+        let kind = match line.origin() {
+            '+' => "addition",
+            '-' => "deletion",
+            ' ' => "context",
+            _ => continue,
+        };
+        lines.push(json!({
+            "type": kind,
+            "old_line": line.old_lineno(),
+            "new_line": line.new_lineno(),
+            "content": String::from_utf8_lossy(line.content()),
+        }));
+    }
+    let header = String::from_utf8_lossy(hunk.header());
+    Ok(json!({
+        "header": header.trim_right(),
+        "old_start": hunk.old_start(),
+        "old_lines": hunk.old_lines(),
+        "new_start": hunk.new_start(),
+        "new_lines": hunk.new_lines(),
+        "lines": lines,
+    }))
+}
+
+/// Quotes the string for `/bin/sh`, in which nothing is expanded inside single quotes.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace("'", "'\\''"))
+}
+
+/// Returns whether the string is a full commit id or an abbreviated one, which is long enough to
+/// be unambiguous in practice.
+fn is_commit_id_prefix(id: &str) -> bool {
+    MIN_COMMIT_ID_PREFIX <= id.len() && id.len() <= 40 && id.chars().all(|c| c.is_digit(16))
+}
+
+/// Splits the path of a file into its components, rejecting paths which cannot be stored in a tree.
+fn split_path(path: &str) -> AppResult<Vec<&str>> {
+    let components: Vec<&str> = path.split('/').collect();
+    if components.iter().any(|&c| c.is_empty() || c == "." || c == ".." || c == ".git") {
+        bail!(AppErrorKind::Invalid(format!("Invalid file path: {}", path)));
+    }
+    Ok(components)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process::{self, Command};
+    use super::{CommitFilter, Repository};
+
+    fn git_command(dir: &Path, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com");
+        command
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = git_command(dir, args).output().unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    fn commit_file(dir: &Path, path: &str, content: &str) -> String {
+        fs::write(dir.join(path), content).unwrap();
+        git(dir, &["add", path]);
+        git(dir, &["commit", "-q", "-m", &format!("Update {}", path)]);
+        git(dir, &["rev-parse", "HEAD"])
+    }
+
+    fn commit_ids(repo: &Repository, path: &str) -> Vec<String> {
+        let filter = CommitFilter {
+            path: Some(path),
+            ..CommitFilter::default()
+        };
+        repo.list_commits("HEAD", &filter, 0, 100)
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit["id"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn path_history_skips_merges_taking_a_parent_entry() {
+        let dir = env::temp_dir().join(format!("gallium-repository-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"]);
+        let root = commit_file(&dir, "a", "1");
+        git(&dir, &["checkout", "-q", "-b", "side"]);
+        let side = commit_file(&dir, "a", "2");
+        git(&dir, &["checkout", "-q", "-"]);
+        let main = commit_file(&dir, "b", "1");
+        git(&dir, &["merge", "-q", "--no-ff", "-m", "Merge side", "side"]);
+        let merge = git(&dir, &["rev-parse", "HEAD"]);
+
+        let repo = Repository::open(&dir).unwrap();
+        assert_eq!(commit_ids(&repo, "a"), vec![side.clone(), root.clone()]);
+        assert_eq!(commit_ids(&repo, "b"), vec![main.clone()]);
+        let last = repo.last_commit_for_path(repo.resolve_commit("HEAD").unwrap().id(), "a").unwrap();
+        assert_eq!(last.map(|id| id.to_string()), Some(side));
+
+        // A merge which resolves the entry differently from both parents touches the path.
+        git(&dir, &["checkout", "-q", "-b", "other", &merge]);
+        commit_file(&dir, "a", "3");
+        git(&dir, &["checkout", "-q", "side"]);
+        commit_file(&dir, "a", "4");
+        let conflicted = git_command(&dir, &["merge", "-q", "other"]).output().unwrap();
+        assert!(!conflicted.status.success(), "the merge must conflict");
+        let resolved = commit_file(&dir, "a", "5");
+        assert_eq!(git(&dir, &["rev-list", "--parents", "-n", "1", "HEAD"]).split(' ').count(), 3);
+        assert_eq!(commit_ids(&repo, "a")[0], resolved);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
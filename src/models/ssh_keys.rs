@@ -1,4 +1,11 @@
+use std::fmt;
+use base64;
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use md5;
+use ring::digest;
+use error::AppResult;
 use super::users::User;
 use schema::ssh_keys;
 
@@ -10,6 +17,8 @@ pub struct SshKey {
     pub key: String,
     pub user_id: i32,
     pub description: Option<String>,
+    pub fingerprint: Option<String>,
+    pub fingerprint_md5: Option<String>,
 }
 
 #[derive(Clone, Debug, Insertable, Deserialize)]
@@ -18,4 +27,361 @@ pub struct NewSshKey {
     pub key: String,
     pub user_id: i32,
     pub description: Option<String>,
+    /// Computed from `key` by `normalize` before inserted.
+    #[serde(skip_deserializing)]
+    pub fingerprint: Option<String>,
+    /// Computed from `key` by `normalize` before inserted.
+    #[serde(skip_deserializing)]
+    pub fingerprint_md5: Option<String>,
+}
+
+impl NewSshKey {
+    /// Validates the submitted key, and replaces it with the normalized form together with its fingerprints.
+    pub fn normalize(&mut self) -> Result<(), String> {
+        let key = PublicKey::parse(&self.key)?;
+        self.key = key.to_string();
+        self.fingerprint = Some(key.fingerprint_sha256());
+        self.fingerprint_md5 = Some(key.fingerprint_md5());
+        Ok(())
+    }
+}
+
+impl SshKey {
+    pub fn find_by_fingerprint(conn: &PgConnection, fingerprint: &str) -> AppResult<Option<Self>> {
+        ssh_keys::table
+            .filter(ssh_keys::dsl::fingerprint.eq(fingerprint))
+            .get_result::<SshKey>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Finds the key by its type (e.g. `ssh-ed25519`) and the base64-encoded body.
+    pub fn find_by_public_key(conn: &PgConnection, key_type: &str, body: &str) -> AppResult<Option<Self>> {
+        let fingerprint = match sha256_fingerprint(body) {
+            Some(fingerprint) => fingerprint,
+            None => return Ok(None),
+        };
+        let keys = ssh_keys::table
+            .filter(ssh_keys::dsl::fingerprint.eq(fingerprint))
+            .load::<SshKey>(&*conn)?;
+        Ok(keys.into_iter().find(|key| {
+            let mut fields = key.key.split_whitespace();
+            fields.next() == Some(key_type) && fields.next() == Some(body)
+        }))
+    }
+
+    /// Finds the key which is identical to the parsed one.
+    pub fn find_by_key(conn: &PgConnection, key: &PublicKey) -> AppResult<Option<Self>> {
+        Self::find_by_public_key(conn, &key.key_type, &base64::encode(&key.blob))
+    }
+
+    /// Computes the fingerprints of the keys registered before they were stored, and returns the
+    /// number of the updated keys.
+    ///
+    /// Keys which fail to be decoded are left without fingerprints, so they are never found.
+    pub fn fill_fingerprints(conn: &PgConnection) -> AppResult<usize> {
+        use schema::ssh_keys::dsl;
+        let keys = ssh_keys::table
+            .filter(dsl::fingerprint.is_null().or(dsl::fingerprint_md5.is_null()))
+            .load::<SshKey>(&*conn)?;
+        let mut count = 0;
+        for key in keys {
+            let blob = match key.key.split_whitespace().nth(1).and_then(|body| base64::decode(body).ok()) {
+                Some(blob) => blob,
+                None => continue,
+            };
+            ::diesel::update(ssh_keys::table.find(key.id))
+                .set((
+                    dsl::fingerprint.eq(format_sha256(&blob)),
+                    dsl::fingerprint_md5.eq(format_md5(&blob)),
+                ))
+                .execute(&*conn)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+
+/// Returns the SHA256 fingerprint of the base64-encoded key, in the same form as `ssh-keygen -l`.
+pub fn sha256_fingerprint(body: &str) -> Option<String> {
+    base64::decode(body).ok().map(|blob| format_sha256(&blob))
+}
+
+fn format_sha256(blob: &[u8]) -> String {
+    let hash = digest::digest(&digest::SHA256, blob);
+    format!("SHA256:{}", base64::encode(hash.as_ref()).trim_right_matches('='))
+}
+
+fn format_md5(blob: &[u8]) -> String {
+    let hash = md5::compute(blob);
+    let hex: Vec<String> = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("MD5:{}", hex.join(":"))
+}
+
+
+/// The minimum size of RSA keys, in bits.
+const MIN_RSA_BITS: u32 = 2048;
+
+
+/// A public key in the form of `authorized_keys` (`<type> <base64> [comment]`), whose options are not allowed.
+#[derive(Debug, Clone)]
+pub struct PublicKey {
+    pub key_type: String,
+    pub blob: Vec<u8>,
+    pub comment: Option<String>,
+    /// The size of the key, in bits.
+    pub bits: u32,
+}
+
+impl PublicKey {
+    /// Parses the key, and rejects unsupported or weak ones.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.contains(|c: char| c == '\n' || c == '\r' || c == '\0') {
+            return Err("The key must be a single line".to_owned());
+        }
+        let mut fields = s.splitn(3, |c: char| c == ' ' || c == '\t');
+        let key_type = fields.next().unwrap_or("");
+        let body = fields.next().ok_or_else(
+            || "The key must be in the form of `<type> <base64>`".to_owned(),
+        )?;
+        let comment = fields
+            .next()
+            .map(|comment| comment.trim().to_owned())
+            .and_then(|comment| if comment.is_empty() { None } else { Some(comment) });
+
+        let blob = base64::decode(body).map_err(|_| "The key is not encoded in base64".to_owned())?;
+        let bits = parse_blob(key_type, &blob)?;
+        if key_type == "ssh-rsa" && bits < MIN_RSA_BITS {
+            return Err(format!("RSA keys must be at least {} bits", MIN_RSA_BITS));
+        }
+
+        Ok(PublicKey {
+            key_type: key_type.to_owned(),
+            blob: blob,
+            comment: comment,
+            bits: bits,
+        })
+    }
+
+    /// Parses the key blob in the wire format of SSH, e.g. the one offered by a client.
+    pub fn from_blob(blob: Vec<u8>) -> Result<Self, String> {
+        let key_type = String::from_utf8(BlobReader(&blob).read_string()?.to_vec())
+            .map_err(|_| "Malformed key type".to_owned())?;
+        let bits = parse_blob(&key_type, &blob)?;
+        Ok(PublicKey {
+            key_type: key_type,
+            blob: blob,
+            comment: None,
+            bits: bits,
+        })
+    }
+
+    /// Returns the SHA256 fingerprint, in the same form as `ssh-keygen -l`.
+    pub fn fingerprint_sha256(&self) -> String {
+        format_sha256(&self.blob)
+    }
+
+    /// Returns the MD5 fingerprint, in the same form as `ssh-keygen -l -E md5`.
+    pub fn fingerprint_md5(&self) -> String {
+        format_md5(&self.blob)
+    }
+}
+
+impl fmt::Display for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.key_type, base64::encode(&self.blob))?;
+        if let Some(ref comment) = self.comment {
+            write!(f, " {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Parses the key blob in the wire format of SSH, and returns the size of the key in bits.
+fn parse_blob(key_type: &str, blob: &[u8]) -> Result<u32, String> {
+    let mut reader = BlobReader(blob);
+    if reader.read_string()? != key_type.as_bytes() {
+        return Err("The type of the key does not match its content".to_owned());
+    }
+    let bits = match key_type {
+        "ssh-rsa" => {
+            let e = reader.read_mpint()?;
+            let n = reader.read_mpint()?;
+            if e.is_empty() || n.is_empty() {
+                return Err("Malformed RSA key".to_owned());
+            }
+            (n.len() as u32 - 1) * 8 + (8 - n[0].leading_zeros())
+        }
+        "ssh-ed25519" => {
+            if reader.read_string()?.len() != 32 {
+                return Err("Malformed Ed25519 key".to_owned());
+            }
+            256
+        }
+        "sk-ssh-ed25519@openssh.com" => {
+            if reader.read_string()?.len() != 32 {
+                return Err("Malformed Ed25519 key".to_owned());
+            }
+            reader.read_string()?;
+            256
+        }
+        "ecdsa-sha2-nistp256" => read_ecdsa(&mut reader, "nistp256", 256)?,
+        "ecdsa-sha2-nistp384" => read_ecdsa(&mut reader, "nistp384", 384)?,
+        "ecdsa-sha2-nistp521" => read_ecdsa(&mut reader, "nistp521", 521)?,
+        "sk-ecdsa-sha2-nistp256@openssh.com" => {
+            let bits = read_ecdsa(&mut reader, "nistp256", 256)?;
+            reader.read_string()?;
+            bits
+        }
+        _ => return Err(format!("Unsupported key type: {}", key_type)),
+    };
+    if !reader.0.is_empty() {
+        return Err("The key has trailing data".to_owned());
+    }
+    Ok(bits)
+}
+
+fn read_ecdsa(reader: &mut BlobReader, curve: &str, bits: u32) -> Result<u32, String> {
+    if reader.read_string()? != curve.as_bytes() {
+        return Err("The curve of the ECDSA key does not match its type".to_owned());
+    }
+    // The point is uncompressed, i.e. `0x04 || x || y`.
+    let point = reader.read_string()?;
+    let coord_len = (bits as usize + 7) / 8;
+    if point.len() != 1 + 2 * coord_len || point[0] != 0x04 {
+        return Err("Malformed ECDSA key".to_owned());
+    }
+    Ok(bits)
+}
+
+struct BlobReader<'a>(&'a [u8]);
+
+impl<'a> BlobReader<'a> {
+    fn read_string(&mut self) -> Result<&'a [u8], String> {
+        let buf = self.0;
+        if buf.len() < 4 {
+            return Err("The key is truncated".to_owned());
+        }
+        let len = ((buf[0] as usize) << 24) | ((buf[1] as usize) << 16) | ((buf[2] as usize) << 8) |
+            (buf[3] as usize);
+        if buf.len() - 4 < len {
+            return Err("The key is truncated".to_owned());
+        }
+        self.0 = &buf[4 + len..];
+        Ok(&buf[4..4 + len])
+    }
+
+    /// Reads a non-negative integer, and returns its big-endian bytes without leading zeros.
+    fn read_mpint(&mut self) -> Result<&'a [u8], String> {
+        let value = self.read_string()?;
+        let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+        Ok(&value[start..])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use base64;
+    use super::PublicKey;
+
+    const ED25519: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHH94T7abenzIAzPRp3O/t11YOoBgC2aKcLZ9Txgoj4U alice@example.com";
+    const RSA_2048: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQC68zaTP0rbL6i/JxyP+LtnywxaaFX1YdSoNUZq6jmp1pOhrLsSQyq77cDfw+EPf1Hq/CN91nNMd64y3IYRfn3BpbWTuckL/AcaVIoaO+lciq9Z+VodiV9HFMUHQMWgQ29xx6RaLvKgscCWSiEWTLGXxslXjbTY4Pvvuj/O9jhxNlSf0d2TA93ClQ0QuMpzBcfOc78wuY0cj4lNY5VwmMcGw/ZjTHM93L48Tf7kPtYdleDO9UsTOkgLb1O7+ik8W+6cRqt3y0jNVSlRCvs7p5R2OZyb7Z6tnl2OBFnG/wcdtY5x/BG/2HYk5a2Mt8J35glKjigpown8CivhP08YhHe7";
+    const RSA_1024: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQDXoyAcXJdBvd8xKjAsE3wcynIS/mAt+7WF1IiyyKSFcFenoA0Z1KT1wP60z/uUxhJWvHaGeKkYULIwfpWyy4ZHwhpuTl2n2O+hMJ6c2lTzPtQyf7pwi83HXNvCQj4PGzctOW9Y+kfQ58NhYdjZ6/PPhTSha9k0QqyDJ/6XUmGinQ==";
+    const ECDSA_P256: &str = "ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNTYAAAAIbmlzdHAyNTYAAABBBIVVvGCf9HV6akniZ9THB9/JBdbD8y/IWU44TYCO8hcSgJY0Xo/eI0j/hSf6jlAv09xOWIj3rENxxKCMTQfKBf8=";
+
+    /// The keys generated by `ssh-keygen`, and their fingerprints reported by `ssh-keygen -l`.
+    const VALID_KEYS: &[(&str, u32, &str, &str)] = &[
+        (
+            ED25519,
+            256,
+            "SHA256:i9gyCGFeBE1ufiaqaLFCb67AFA+Qxkczi6syz9fFpc0",
+            "MD5:db:98:a5:12:a7:09:03:fd:f9:cd:f9:6e:55:3e:30:2d",
+        ),
+        (
+            RSA_2048,
+            2048,
+            "SHA256:J9M5i8OTOnpPqlUoL4k2TWBgRfeUjxdYLbgywXpZukQ",
+            "MD5:96:05:c4:23:82:0d:67:d8:ff:2b:da:8c:94:9f:f8:ba",
+        ),
+        (
+            ECDSA_P256,
+            256,
+            "SHA256:e8eMexKBqJL1o7e9LAeLvQl1LR70qSTUKnzzLSON6UQ",
+            "MD5:f3:96:61:f5:74:e5:87:d6:83:47:92:34:94:fc:d4:28",
+        ),
+    ];
+
+    #[test]
+    fn parse_valid_keys() {
+        for &(s, bits, sha256, md5) in VALID_KEYS {
+            let key = PublicKey::parse(s).expect(s);
+            assert_eq!(key.bits, bits, "{}", s);
+            assert_eq!(key.fingerprint_sha256(), sha256, "{}", s);
+            assert_eq!(key.fingerprint_md5(), md5, "{}", s);
+            assert_eq!(key.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn from_blob_matches_parse() {
+        for &(s, bits, sha256, _) in VALID_KEYS {
+            let blob = base64::decode(s.split(' ').nth(1).unwrap()).unwrap();
+            let key = PublicKey::from_blob(blob).expect(s);
+            assert_eq!(key.key_type, s.split(' ').next().unwrap());
+            assert_eq!(key.bits, bits, "{}", s);
+            assert_eq!(key.fingerprint_sha256(), sha256, "{}", s);
+            assert_eq!(key.comment, None);
+        }
+    }
+
+    #[test]
+    fn parse_normalizes_whitespace_and_comment() {
+        let key = PublicKey::parse(&format!("  {}  \n", ED25519.replacen(' ', "\t", 1))).unwrap();
+        assert_eq!(key.to_string(), ED25519);
+        assert_eq!(key.comment, Some("alice@example.com".to_owned()));
+
+        let body = ED25519.rsplitn(2, ' ').nth(1).unwrap();
+        let key = PublicKey::parse(&format!("{}   ", body)).unwrap();
+        assert_eq!(key.comment, None);
+        assert_eq!(key.to_string(), body);
+    }
+
+    #[test]
+    fn parse_invalid_keys() {
+        let ed25519_body = ED25519.split(' ').nth(1).unwrap();
+        let truncated = &ed25519_body[..ed25519_body.len() - 8];
+        let blob = base64::decode(ed25519_body).unwrap();
+        let trailing = base64::encode(&[&blob[..], &[0u8; 4][..]].concat());
+
+        let cases = vec![
+            ("", "empty"),
+            ("ssh-ed25519", "no body"),
+            (RSA_1024, "1024-bit RSA"),
+            (&format!("no-pty,command=\"sh\" {}", ED25519), "options"),
+            (&format!("ssh-ed25519 {}", truncated), "truncated"),
+            (&format!("ssh-ed25519 {}", trailing), "trailing data"),
+            (&format!("ssh-rsa {}", ed25519_body), "mismatched type"),
+            ("ssh-ed25519 !!!!", "not base64"),
+            (&format!("ssh-dss {}", ed25519_body), "unsupported type"),
+            (&format!("{}\nssh-ed25519 {}", ED25519, ed25519_body), "multiple lines"),
+        ].into_iter()
+            .map(|(s, name)| (s.to_owned(), name))
+            .collect::<Vec<_>>();
+        for (s, name) in cases {
+            assert!(PublicKey::parse(&s).is_err(), "{} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn from_blob_rejects_malformed_blobs() {
+        let blob = base64::decode(ED25519.split(' ').nth(1).unwrap()).unwrap();
+        assert!(PublicKey::from_blob(Vec::new()).is_err());
+        assert!(PublicKey::from_blob(blob[..blob.len() - 1].to_vec()).is_err());
+        assert!(PublicKey::from_blob([&blob[..], &[0u8][..]].concat()).is_err());
+        assert!(PublicKey::from_blob(vec![0, 0, 0, 255, b's']).is_err());
+    }
 }
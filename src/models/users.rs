@@ -5,13 +5,16 @@ use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use iron::typemap::Key;
 
-use error::AppResult;
-use schema::{users, ssh_keys, projects};
+use error::{AppResult, AppError};
+use schema::{users, ssh_keys, projects, personal_access_tokens, organizations, lfs_locks};
+use super::organizations::validate_namespace;
 
 
 #[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
 #[has_many(ssh_keys)]
 #[has_many(projects)]
+#[has_many(personal_access_tokens)]
+#[has_many(lfs_locks)]
 pub struct User {
     pub id: i32,
     pub created_at: NaiveDateTime,
@@ -31,6 +34,17 @@ struct NewUser<'a> {
 
 impl User {
     pub fn create(conn: &PgConnection, name: &str, password: &str, screen_name: Option<&str>) -> AppResult<Self> {
+        validate_namespace(name)?;
+        let taken = organizations::table
+            .filter(organizations::dsl::name.eq(name))
+            .count()
+            .get_result::<i64>(&*conn)?;
+        if taken > 0 {
+            return Err(AppError::from(
+                format!("The namespace \"{}\" is already taken", name),
+            ));
+        }
+
         let bcrypt_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)?;
         let new_user = NewUser {
             name: name,
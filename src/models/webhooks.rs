@@ -0,0 +1,215 @@
+use chrono::NaiveDateTime;
+use diesel::{insert, update, delete};
+use diesel::expression::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::types::{Bool, Nullable, Timestamp};
+
+use error::{AppResult, AppError};
+use schema::{webhooks, webhook_deliveries};
+use super::projects::Project;
+
+
+pub const EVENT_PUSH: &'static str = "push";
+pub const EVENT_TAG_PUSH: &'static str = "tag_push";
+pub const EVENT_PROJECT_CREATED: &'static str = "project_created";
+pub const EVENT_PROJECT_DELETED: &'static str = "project_deleted";
+pub const EVENT_MEMBER_ADDED: &'static str = "member_added";
+
+const EVENTS: &'static [&'static str] = &[
+    EVENT_PUSH,
+    EVENT_TAG_PUSH,
+    EVENT_PROJECT_CREATED,
+    EVENT_PROJECT_DELETED,
+    EVENT_MEMBER_ADDED,
+];
+
+/// The maximum number of attempts to deliver a payload.
+pub const MAX_ATTEMPTS: i32 = 6;
+
+
+#[derive(Debug, Queryable, Identifiable, Associations, AsChangeset)]
+#[belongs_to(Project)]
+pub struct Webhook {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub project_id: i32,
+    pub url: String,
+    pub secret: Option<String>,
+    pub events: Vec<String>,
+    pub active: bool,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "webhooks"]
+pub struct NewWebhook {
+    pub project_id: i32,
+    pub url: String,
+    pub secret: Option<String>,
+    pub events: Vec<String>,
+}
+
+/// Partial update of a webhook. Fields set to `None` are left unchanged.
+#[derive(Clone, Debug, Default, AsChangeset)]
+#[table_name = "webhooks"]
+pub struct WebhookChangeset {
+    pub url: Option<String>,
+    /// `Some(None)` removes the secret.
+    pub secret: Option<Option<String>>,
+    pub events: Option<Vec<String>>,
+    pub active: Option<bool>,
+}
+
+fn validate_events(events: &[String]) -> AppResult<()> {
+    if let Some(event) = events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+        return Err(AppError::from(format!("Unknown event: {}", event)));
+    }
+    Ok(())
+}
+
+impl NewWebhook {
+    pub fn insert(&self, conn: &PgConnection) -> AppResult<Webhook> {
+        validate_events(&self.events)?;
+        insert(self)
+            .into(webhooks::table)
+            .get_result::<Webhook>(&*conn)
+            .map_err(Into::into)
+    }
+}
+
+impl Webhook {
+    pub fn load_by_project(conn: &PgConnection, project_id: i32) -> AppResult<Vec<Self>> {
+        webhooks::table
+            .filter(webhooks::dsl::project_id.eq(project_id))
+            .order(webhooks::dsl::id)
+            .load::<Webhook>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn find(conn: &PgConnection, project_id: i32, id: i32) -> AppResult<Option<Self>> {
+        webhooks::table
+            .filter(webhooks::dsl::project_id.eq(project_id))
+            .filter(webhooks::dsl::id.eq(id))
+            .get_result::<Webhook>(&*conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn update(&self, conn: &PgConnection, changeset: &WebhookChangeset) -> AppResult<Self> {
+        if let Some(ref events) = changeset.events {
+            validate_events(events)?;
+        }
+        update(webhooks::table.filter(webhooks::dsl::id.eq(self.id)))
+            .set(changeset)
+            .get_result::<Webhook>(&*conn)
+            .map_err(Into::into)
+    }
+
+    pub fn remove(&self, conn: &PgConnection) -> AppResult<()> {
+        delete(webhooks::table.filter(webhooks::dsl::id.eq(self.id)))
+            .execute(&*conn)?;
+        Ok(())
+    }
+
+    pub fn is_subscribed(&self, event: &str) -> bool {
+        self.active && self.events.iter().any(|e| e == event)
+    }
+}
+
+
+#[derive(Debug, Queryable, Identifiable, AsChangeset)]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub webhook_id: Option<i32>,
+    pub event: String,
+    pub url: String,
+    pub payload: String,
+    pub signature: Option<String>,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub status_code: Option<i32>,
+    pub response_body: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery {
+    pub webhook_id: Option<i32>,
+    pub event: String,
+    pub url: String,
+    pub payload: String,
+    pub signature: Option<String>,
+}
+
+impl NewWebhookDelivery {
+    pub fn insert(&self, conn: &PgConnection) -> AppResult<WebhookDelivery> {
+        insert(self)
+            .into(webhook_deliveries::table)
+            .get_result::<WebhookDelivery>(&*conn)
+            .map_err(Into::into)
+    }
+}
+
+/// Returns the delay before the next attempt after the given number of failed ones, in seconds.
+pub fn retry_delay(attempts: i32) -> i64 {
+    30 * (1 << attempts.max(0).min(10))
+}
+
+impl WebhookDelivery {
+    pub fn load_by_webhook(conn: &PgConnection, webhook_id: i32) -> AppResult<Vec<Self>> {
+        webhook_deliveries::table
+            .filter(webhook_deliveries::dsl::webhook_id.eq(webhook_id))
+            .order(webhook_deliveries::dsl::id.desc())
+            .limit(100)
+            .load::<WebhookDelivery>(&*conn)
+            .map_err(Into::into)
+    }
+
+    /// Loads the deliveries which are neither delivered nor given up, and whose next attempt is due.
+    pub fn load_pending(conn: &PgConnection) -> AppResult<Vec<Self>> {
+        webhook_deliveries::table
+            .filter(webhook_deliveries::dsl::delivered_at.is_null())
+            .filter(webhook_deliveries::dsl::attempts.lt(MAX_ATTEMPTS))
+            .filter(sql::<Bool>("webhook_deliveries.next_attempt_at <= CURRENT_TIMESTAMP"))
+            .order(webhook_deliveries::dsl::id)
+            .limit(100)
+            .load::<WebhookDelivery>(&*conn)
+            .map_err(Into::into)
+    }
+
+    /// Records the successful attempt.
+    pub fn mark_delivered(&self, conn: &PgConnection, status_code: i32, response_body: &str) -> AppResult<()> {
+        use schema::webhook_deliveries::dsl;
+        update(webhook_deliveries::table.filter(dsl::id.eq(self.id)))
+            .set((
+                dsl::attempts.eq(self.attempts + 1),
+                dsl::delivered_at.eq(sql::<Nullable<Timestamp>>("CURRENT_TIMESTAMP")),
+                dsl::status_code.eq(Some(status_code)),
+                dsl::response_body.eq(Some(response_body)),
+                dsl::error.eq(None::<String>),
+            ))
+            .execute(&*conn)?;
+        Ok(())
+    }
+
+    /// Records the failed attempt, and schedules the next one with exponential backoff.
+    pub fn mark_failed(&self, conn: &PgConnection, status_code: Option<i32>, error: &str) -> AppResult<()> {
+        use schema::webhook_deliveries::dsl;
+        let backoff = retry_delay(self.attempts);
+        update(webhook_deliveries::table.filter(dsl::id.eq(self.id)))
+            .set((
+                dsl::attempts.eq(self.attempts + 1),
+                dsl::next_attempt_at.eq(sql::<Timestamp>(
+                    &format!("CURRENT_TIMESTAMP + interval '{} seconds'", backoff),
+                )),
+                dsl::status_code.eq(status_code),
+                dsl::error.eq(Some(error)),
+            ))
+            .execute(&*conn)?;
+        Ok(())
+    }
+}
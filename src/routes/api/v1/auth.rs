@@ -0,0 +1,48 @@
+use bodyparser::Struct;
+use iron::prelude::*;
+
+use auth::{self, TOKEN_LIFETIME};
+use config::Config;
+use db::DB;
+use models::User;
+use super::{response, error};
+
+
+/// Returns the user authenticated by `AuthMiddleware`, or an error if the request is anonymous.
+pub(super) fn require_user<'a>(req: &'a Request) -> IronResult<&'a User> {
+    req.extensions.get::<User>().ok_or_else(|| {
+        error::unauthorized("Authentication is required")
+    })
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/login", handler = "login")]
+pub(super) struct Login;
+
+fn login(req: &mut Request) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        username: String,
+        password: String,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user = User::authenticate(&conn, &params.username, &params.password)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::unauthorized("Invalid username or password"))?;
+
+    let config = req.extensions.get::<Config>().unwrap().clone();
+    let token = auth::issue_token(&config, &user).map_err(error::server_error)?;
+
+    response::ok(json!({
+        "access_token": token,
+        "token_type": "bearer",
+        "expires_in": TOKEN_LIFETIME,
+    }))
+}
@@ -0,0 +1,124 @@
+use bodyparser::Struct;
+use iron::prelude::*;
+
+use db::DB;
+use hooks::ZERO_OID;
+use models::protected_branches::ProtectedBranch;
+use models::projects::AccessLevel;
+use super::{response, error};
+use super::auth::require_user;
+use super::files::decode_path;
+use super::projects::find_project;
+use super::repository::{repository_error, check_protected_branch, notify_ref_update};
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/branches", handler = "get_branches")]
+pub(super) struct GetBranches;
+
+fn get_branches(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+
+    let rules = ProtectedBranch::load_by_project(&conn, project.id).map_err(
+        error::server_error,
+    )?;
+    let mut branches = repo.list_branches().map_err(error::server_error)?;
+    for branch in &mut branches {
+        let protected = branch["name"].as_str().map_or(false, |name| {
+            rules.iter().any(|rule| rule.matches(name))
+        });
+        branch["protected"] = json!(protected);
+    }
+
+    response::ok(branches)
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/branches/*branch", handler = "get_branch")]
+pub(super) struct GetBranch;
+
+fn get_branch(req: &mut Request, id: i32, branch: String) -> IronResult<Response> {
+    let branch = decode_path(&branch);
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+
+    let mut item = repo.get_branch(&branch)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The branch does not exist"))?;
+    let protected = ProtectedBranch::find_matching(&conn, project.id, &branch)
+        .map_err(error::server_error)?
+        .is_some();
+    item["protected"] = json!(protected);
+
+    response::ok(item)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/repository/branches", handler = "create_branch")]
+pub(super) struct CreateBranch;
+
+fn create_branch(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        branch: String,
+        #[serde(rename = "ref")]
+        revision: String,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Write)?;
+    let user = require_user(req)?;
+    check_protected_branch(&conn, &project, user, &params.branch, false)?;
+
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    if repo.get_branch(&params.branch).map_err(error::server_error)?.is_some() {
+        return Err(error::bad_request("The branch already exists"));
+    }
+    let branch = repo.create_branch(&params.branch, &params.revision)
+        .map_err(repository_error)?;
+
+    if let Some(commit) = branch["commit"]["id"].as_str() {
+        let refname = format!("refs/heads/{}", params.branch);
+        notify_ref_update(&conn, &project, user, &repo, &refname, ZERO_OID, commit);
+    }
+
+    response::created(branch)
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/projects/:id/repository/branches/*branch", handler = "delete_branch")]
+pub(super) struct DeleteBranch;
+
+fn delete_branch(req: &mut Request, id: i32, branch: String) -> IronResult<Response> {
+    let branch = decode_path(&branch);
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Write)?;
+    let user = require_user(req)?;
+    check_protected_branch(&conn, &project, user, &branch, true)?;
+
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    if repo.default_branch().map_err(error::server_error)?.as_ref() == Some(&branch) {
+        return Err(error::bad_request("The default branch cannot be deleted"));
+    }
+    let old = repo.delete_branch(&branch)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The branch does not exist"))?;
+
+    let refname = format!("refs/heads/{}", branch);
+    notify_ref_update(&conn, &project, user, &repo, &refname, &old, ZERO_OID);
+
+    response::no_content()
+}
@@ -5,6 +5,7 @@ use iron::status;
 use serde_json;
 use std::error::Error;
 use error::AppError;
+use routes::WWWAuthenticate;
 
 pub(super) fn not_found(message: &str) -> IronError {
     let body = serde_json::to_string(&json!({
@@ -25,7 +26,7 @@ pub(super) fn bad_request(message: &str) -> IronError {
         "error_description": message,
     })).unwrap_or("{}".into());
     IronError::new(AppError::from(message), (
-        status::NotFound,
+        status::BadRequest,
         Header(ContentType::json()),
         body,
     ))
@@ -38,7 +39,56 @@ pub(super) fn server_error<E: 'static + Error + Send>(err: E) -> IronError {
         "error_description": message,
     })).unwrap_or("{}".into());
     IronError::new(AppError::from(message), (
-        status::NotFound,
+        status::InternalServerError,
+        Header(ContentType::json()),
+        body,
+    ))
+}
+
+pub(super) fn unauthorized(message: &str) -> IronError {
+    let body = serde_json::to_string(&json!({
+        "error": "unauthorized",
+        "error_description": message,
+    })).unwrap_or("{}".into());
+    IronError::new(AppError::from(message), (
+        status::Unauthorized,
+        Header(ContentType::json()),
+        Header(WWWAuthenticate("Bearer realm=\"main\"".to_owned())),
+        body,
+    ))
+}
+
+pub(super) fn forbidden(message: &str) -> IronError {
+    let body = serde_json::to_string(&json!({
+        "error": "forbidden",
+        "error_description": message,
+    })).unwrap_or("{}".into());
+    IronError::new(AppError::from(message), (
+        status::Forbidden,
+        Header(ContentType::json()),
+        body,
+    ))
+}
+
+pub(super) fn conflict(message: &str) -> IronError {
+    let body = serde_json::to_string(&json!({
+        "error": "conflict",
+        "error_description": message,
+    })).unwrap_or("{}".into());
+    IronError::new(AppError::from(message), (
+        status::Conflict,
+        Header(ContentType::json()),
+        body,
+    ))
+}
+
+pub(super) fn payload_too_large(message: &str) -> IronError {
+    let body = serde_json::to_string(&json!({
+        "error": "payload_too_large",
+        "error_description": message,
+    })).unwrap_or("{}".into());
+    IronError::new(AppError::from(message), (
+        status::PayloadTooLarge,
         Header(ContentType::json()),
         body,
     ))
@@ -0,0 +1,196 @@
+use std::borrow::Borrow;
+use std::path::Path;
+use bodyparser::Struct;
+use iron::prelude::*;
+use base64;
+use serde_json::Value as JsonValue;
+use url::Url;
+use url::percent_encoding::percent_decode;
+
+use config::Config;
+use db::DB;
+use hooks::ZERO_OID;
+use models::projects::AccessLevel;
+use models::Repository;
+use models::repository::{is_binary, File, FileAction, FileActionKind};
+use super::{response, error};
+use super::auth::require_user;
+use super::projects::find_project;
+use super::raw::raw_blob_response;
+use super::repository::{repository_error, email_of, signature_of, check_protected_branch, notify_ref_update};
+
+
+#[derive(Clone, Deserialize)]
+struct Params {
+    branch: String,
+    content: Option<String>,
+    encoding: Option<String>,
+    commit_message: String,
+    last_commit_id: Option<String>,
+}
+
+/// Decodes the content of a file given in the encoding, either `text` (default) or `base64`.
+pub(super) fn decode_content(content: String, encoding: Option<&str>) -> IronResult<Vec<u8>> {
+    match encoding.unwrap_or("text") {
+        "text" => Ok(content.into_bytes()),
+        "base64" => base64::decode(&content).map_err(|_| error::bad_request("invalid base64 content")),
+        _ => Err(error::bad_request("`encoding` must be either `text` or `base64`")),
+    }
+}
+
+
+/// Decodes the path captured from the URL (e.g. of a file or a branch), in which some characters
+/// are percent-encoded.
+pub(super) fn decode_path(path: &str) -> String {
+    percent_decode(path.as_bytes()).decode_utf8_lossy().into_owned()
+}
+
+/// Finds the file at the path in the revision given by the query parameter `ref`, which defaults to `HEAD`.
+///
+/// The content is omitted if it is larger than `max_size`.
+fn find_file(req: &mut Request, id: i32, path: &str, max_size: Option<usize>) -> IronResult<(Repository, File)> {
+    let mut refname = None;
+    let url: Url = req.url.clone().into();
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "ref" => refname = Some(val.into_owned()),
+            _ => (),
+        }
+    }
+    let refname = refname.unwrap_or_else(|| "HEAD".to_owned());
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    let file = repo.find_file(&refname, &decode_path(path), max_size)
+        .map_err(repository_error)?;
+    Ok((repo, file))
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/files/*path", handler = "get_file")]
+pub(super) struct GetFile;
+
+fn get_file(req: &mut Request, id: i32, path: String) -> IronResult<Response> {
+    // The raw variant cannot be registered as another route, since the router prefers the route
+    // with fewer static segments. The path is still percent-encoded here, so a file named `raw`
+    // can be retrieved as `<dir>%2Fraw`.
+    if path.ends_with("/raw") {
+        return get_raw_file(req, id, &path[..path.len() - "/raw".len()]);
+    }
+
+    let max_size = req.extensions.get::<Config>().unwrap().max_inline_blob_size;
+    let (repo, mut file) = find_file(req, id, &path, Some(max_size))?;
+    let content = file.content.take().ok_or_else(|| {
+        error::payload_too_large(&format!("The file is larger than {} bytes, use the raw endpoint instead", max_size))
+    })?;
+    let last_commit_id = repo.last_commit_for_file(&file).map_err(error::server_error)?;
+
+    response::ok(json!({
+        "file_name": Path::new(&file.path).file_name().map(|name| name.to_string_lossy()),
+        "file_path": file.path,
+        "blob_id": file.blob_id,
+        "mode": file.mode,
+        "commit_id": file.commit_id,
+        "last_commit_id": last_commit_id,
+        "size": file.size,
+        "binary": is_binary(&content),
+        "encoding": "base64",
+        "content": base64::encode(&content),
+    }))
+}
+
+
+/// Returns the raw content of the file, for `/projects/:id/repository/files/*path/raw`.
+fn get_raw_file(req: &mut Request, id: i32, path: &str) -> IronResult<Response> {
+    let (_, file) = find_file(req, id, &path, None)?;
+    raw_blob_response(req, &file.blob_id, Some(&file.path), file.content.unwrap_or_default())
+}
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/repository/files/*path", handler = "create_file")]
+pub(super) struct CreateFile;
+
+fn create_file(req: &mut Request, id: i32, path: String) -> IronResult<Response> {
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+    let content = params.content.clone().ok_or_else(
+        || error::bad_request("`content` is required"),
+    )?;
+    let content = decode_content(content, params.encoding.as_ref().map(|s| s.as_str()))?;
+    let file = commit_file(req, id, path, FileActionKind::Create(content), params)?;
+    response::created(file)
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/projects/:id/repository/files/*path", handler = "update_file")]
+pub(super) struct UpdateFile;
+
+fn update_file(req: &mut Request, id: i32, path: String) -> IronResult<Response> {
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+    let content = params.content.clone().ok_or_else(
+        || error::bad_request("`content` is required"),
+    )?;
+    let content = decode_content(content, params.encoding.as_ref().map(|s| s.as_str()))?;
+    let file = commit_file(req, id, path, FileActionKind::Update(content), params)?;
+    response::ok(file)
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/projects/:id/repository/files/*path", handler = "delete_file")]
+pub(super) struct DeleteFile;
+
+fn delete_file(req: &mut Request, id: i32, path: String) -> IronResult<Response> {
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+    commit_file(req, id, path, FileActionKind::Delete, params)?;
+    response::no_content()
+}
+
+
+/// Commits the action to the file, and returns the description of the commit.
+fn commit_file(req: &mut Request, id: i32, path: String, kind: FileActionKind, params: Params) -> IronResult<JsonValue> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Write)?;
+    let user = require_user(req)?;
+    check_protected_branch(&conn, &project, user, &params.branch, false)?;
+
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    let path = decode_path(&path);
+    let action = FileAction {
+        path: path.clone(),
+        kind: kind,
+        last_commit_id: params.last_commit_id,
+    };
+    let email = email_of(user);
+    let (old, new) = repo.commit_files(
+        &params.branch,
+        None,
+        &[action],
+        &params.commit_message,
+        &signature_of(user, &email),
+    ).map_err(repository_error)?;
+
+    let refname = format!("refs/heads/{}", params.branch);
+    let old = old.as_ref().map(|s| s.as_str()).unwrap_or(ZERO_OID);
+    notify_ref_update(&conn, &project, user, &repo, &refname, old, &new);
+
+    Ok(json!({
+        "file_path": path,
+        "branch": params.branch,
+        "commit_id": new,
+    }))
+}
@@ -0,0 +1,172 @@
+use bodyparser::Struct;
+use diesel::pg::PgConnection;
+use iron::prelude::*;
+
+use db::DB;
+use models::{User, Project, ProjectMember};
+use models::project_members::Role;
+use models::projects::AccessLevel;
+use models::webhooks::EVENT_MEMBER_ADDED;
+use webhooks;
+use super::{response, error};
+use super::auth::require_user;
+use super::projects::find_project;
+
+
+/// Checks whether the current user is allowed to grant (or revoke) the role.
+///
+/// Only owners may manage other owners.
+fn check_grantable(req: &Request, conn: &PgConnection, project: &Project, role: Role) -> IronResult<()> {
+    if role == Role::Owner {
+        let user_id = require_user(req)?.id;
+        if project.role_of(conn, user_id).map_err(error::server_error)? != Some(Role::Owner) {
+            return Err(error::forbidden("Only the owner can manage owners"));
+        }
+    }
+    Ok(())
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/members", handler = "get_members")]
+pub(super) struct GetMembers;
+
+fn get_members(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+
+    let members: Vec<EncodableMember> = ProjectMember::load_by_project(&conn, project.id)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(members)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/members", handler = "add_member")]
+pub(super) struct AddMember;
+
+fn add_member(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        user_id: i32,
+        role: Role,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+    check_grantable(req, &conn, &project, params.role)?;
+
+    if project.organization_id.is_none() && params.user_id == project.user_id {
+        return Err(error::bad_request("The owner of the project cannot be added as a member"));
+    }
+    User::find_by_id(&conn, params.user_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::bad_request("The user does not exist"))?;
+    if ProjectMember::find(&conn, project.id, params.user_id)
+        .map_err(error::server_error)?
+        .is_some()
+    {
+        return Err(error::bad_request("The user is already a member of the project"));
+    }
+
+    let member = ProjectMember::add(&conn, project.id, params.user_id, params.role)
+        .map_err(error::server_error)?;
+
+    let payload = json!({
+        "project": webhooks::project_payload(&conn, &project).map_err(error::server_error)?,
+        "user": webhooks::user_payload(require_user(req)?),
+        "member": {
+            "user_id": member.user_id,
+            "role": member.role,
+        },
+    });
+    webhooks::trigger(&conn, project.id, EVENT_MEMBER_ADDED, payload)
+        .map_err(error::server_error)?;
+
+    response::created(EncodableMember::from(member))
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/projects/:id/members/:user_id", handler = "update_member")]
+pub(super) struct UpdateMember;
+
+fn update_member(req: &mut Request, id: i32, user_id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        role: Role,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let member = ProjectMember::find(&conn, project.id, user_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The member does not exist"))?;
+    check_grantable(req, &conn, &project, member.role())?;
+    check_grantable(req, &conn, &project, params.role)?;
+
+    let member = member.update_role(&conn, params.role).map_err(
+        error::server_error,
+    )?;
+
+    response::ok(EncodableMember::from(member))
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/projects/:id/members/:user_id", handler = "remove_member")]
+pub(super) struct RemoveMember;
+
+fn remove_member(req: &mut Request, id: i32, user_id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let member = ProjectMember::find(&conn, project.id, user_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The member does not exist"))?;
+    check_grantable(req, &conn, &project, member.role())?;
+
+    member.remove(&conn).map_err(error::server_error)?;
+
+    response::no_content()
+}
+
+
+
+#[derive(Serialize)]
+pub struct EncodableMember {
+    id: i32,
+    created_at: String,
+    project_id: i32,
+    user_id: i32,
+    role: String,
+}
+
+impl From<ProjectMember> for EncodableMember {
+    fn from(val: ProjectMember) -> Self {
+        EncodableMember {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            project_id: val.project_id,
+            user_id: val.user_id,
+            role: val.role,
+        }
+    }
+}
@@ -1,29 +1,87 @@
 mod error;
 mod response;
 
+mod auth;
+mod branches;
+mod files;
+mod members;
+mod organizations;
+mod personal_access_tokens;
 mod ssh_keys;
 mod projects;
+mod protected_branches;
+mod raw;
 mod repository;
+mod tags;
 mod users;
+mod webhooks;
 
 use router::Router;
 use iron_router_ext::RegisterRoute;
 
 pub fn create_api_router() -> Router {
     let mut router = Router::new();
+    router.register(auth::Login);
+    router.register(branches::GetBranches);
+    router.register(branches::GetBranch);
+    router.register(branches::CreateBranch);
+    router.register(branches::DeleteBranch);
+    router.register(files::GetFile);
+    router.register(files::CreateFile);
+    router.register(files::UpdateFile);
+    router.register(files::DeleteFile);
+    router.register(members::GetMembers);
+    router.register(members::AddMember);
+    router.register(members::UpdateMember);
+    router.register(members::RemoveMember);
+    router.register(organizations::GetOrganizations);
+    router.register(organizations::GetOrganization);
+    router.register(organizations::CreateOrganization);
+    router.register(organizations::UpdateOrganization);
+    router.register(organizations::DeleteOrganization);
+    router.register(organizations::GetOrganizationMembers);
+    router.register(organizations::AddOrganizationMember);
+    router.register(organizations::UpdateOrganizationMember);
+    router.register(organizations::RemoveOrganizationMember);
+    router.register(personal_access_tokens::GetTokens);
+    router.register(personal_access_tokens::CreateToken);
+    router.register(personal_access_tokens::RevokeToken);
     router.register(projects::GetProjects);
     router.register(projects::GetProject);
     router.register(projects::CreateProject);
+    router.register(projects::UpdateProject);
     router.register(projects::DeleteProject);
+    router.register(protected_branches::GetProtectedBranches);
+    router.register(protected_branches::CreateProtectedBranch);
+    router.register(protected_branches::UpdateProtectedBranch);
+    router.register(protected_branches::DeleteProtectedBranch);
     router.register(repository::ShowTree);
     router.register(repository::GetBlob);
     router.register(repository::GetRawBlob);
+    router.register(repository::GetCommits);
+    router.register(repository::GetCommit);
+    router.register(repository::CreateCommit);
+    router.register(repository::GetCommitDiff);
+    router.register(repository::Compare);
+    router.register(repository::GetBlame);
+    router.register(repository::GetArchiveTarGz);
+    router.register(repository::GetArchiveTar);
+    router.register(repository::GetArchiveZip);
     router.register(ssh_keys::GetKeys);
     router.register(ssh_keys::GetKey);
     router.register(ssh_keys::AddKey);
     router.register(ssh_keys::DeleteKey);
+    router.register(tags::GetTags);
+    router.register(tags::GetTag);
+    router.register(tags::CreateTag);
+    router.register(tags::DeleteTag);
     router.register(users::GetUsers);
     router.register(users::GetUser);
     router.register(users::CreateUser);
+    router.register(webhooks::GetWebhooks);
+    router.register(webhooks::CreateWebhook);
+    router.register(webhooks::UpdateWebhook);
+    router.register(webhooks::DeleteWebhook);
+    router.register(webhooks::GetWebhookDeliveries);
     router
 }
@@ -0,0 +1,287 @@
+use bodyparser::Struct;
+use diesel::pg::PgConnection;
+use iron::prelude::*;
+
+use db::DB;
+use models::{User, Organization, NewOrganization, OrganizationMember};
+use models::project_members::Role;
+use super::{response, error};
+use super::auth::require_user;
+
+
+fn find_organization(conn: &PgConnection, id: i32) -> IronResult<Organization> {
+    Organization::find_by_id(conn, id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The organization does not exist"))
+}
+
+/// Checks whether the current user has the required role (or higher) in the organization.
+fn check_role(req: &Request, conn: &PgConnection, org: &Organization, required: Role) -> IronResult<()> {
+    let user_id = require_user(req)?.id;
+    let role = OrganizationMember::find(conn, org.id, user_id)
+        .map_err(error::server_error)?
+        .map(|m| m.role());
+    if role < Some(required) {
+        return Err(error::forbidden("Insufficient permission to the organization"));
+    }
+    Ok(())
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/orgs", handler = "get_organizations")]
+pub(super) struct GetOrganizations;
+
+fn get_organizations(req: &mut Request) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let orgs: Vec<EncodableOrganization> = Organization::load_organizations(&conn)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(orgs)
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/orgs/:id", handler = "get_organization")]
+pub(super) struct GetOrganization;
+
+fn get_organization(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+
+    response::ok(EncodableOrganization::from(org))
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/orgs", handler = "create_organization")]
+pub(super) struct CreateOrganization;
+
+fn create_organization(req: &mut Request) -> IronResult<Response> {
+    let new_org = req.get::<Struct<NewOrganization>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user_id = require_user(req)?.id;
+
+    let org = new_org.insert(&conn, user_id).map_err(
+        |err| error::bad_request(&err.to_string()),
+    )?;
+
+    response::created(EncodableOrganization::from(org))
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/orgs/:id", handler = "update_organization")]
+pub(super) struct UpdateOrganization;
+
+fn update_organization(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        display_name: Option<String>,
+        description: Option<String>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+    check_role(req, &conn, &org, Role::Maintainer)?;
+
+    let org = org.update(
+        &conn,
+        params.display_name.as_ref().map(|s| s.as_str()),
+        params.description.as_ref().map(|s| s.as_str()),
+    ).map_err(error::server_error)?;
+
+    response::ok(EncodableOrganization::from(org))
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/orgs/:id", handler = "delete_organization")]
+pub(super) struct DeleteOrganization;
+
+fn delete_organization(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+    check_role(req, &conn, &org, Role::Owner)?;
+
+    org.remove(&conn).map_err(|err| error::bad_request(&err.to_string()))?;
+
+    response::no_content()
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/orgs/:id/members", handler = "get_organization_members")]
+pub(super) struct GetOrganizationMembers;
+
+fn get_organization_members(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+
+    let members: Vec<EncodableOrganizationMember> = OrganizationMember::load_by_organization(&conn, org.id)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(members)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/orgs/:id/members", handler = "add_organization_member")]
+pub(super) struct AddOrganizationMember;
+
+fn add_organization_member(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        user_id: i32,
+        role: Role,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+    check_role(req, &conn, &org, Role::Maintainer)?;
+    if params.role == Role::Owner {
+        check_role(req, &conn, &org, Role::Owner)?;
+    }
+
+    User::find_by_id(&conn, params.user_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::bad_request("The user does not exist"))?;
+    if OrganizationMember::find(&conn, org.id, params.user_id)
+        .map_err(error::server_error)?
+        .is_some()
+    {
+        return Err(error::bad_request("The user is already a member of the organization"));
+    }
+
+    let member = OrganizationMember::add(&conn, org.id, params.user_id, params.role)
+        .map_err(error::server_error)?;
+
+    response::created(EncodableOrganizationMember::from(member))
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/orgs/:id/members/:user_id", handler = "update_organization_member")]
+pub(super) struct UpdateOrganizationMember;
+
+fn update_organization_member(req: &mut Request, id: i32, user_id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        role: Role,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+    check_role(req, &conn, &org, Role::Maintainer)?;
+
+    let member = OrganizationMember::find(&conn, org.id, user_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The member does not exist"))?;
+    if member.role() == Role::Owner || params.role == Role::Owner {
+        check_role(req, &conn, &org, Role::Owner)?;
+    }
+
+    let member = member.update_role(&conn, params.role).map_err(
+        error::server_error,
+    )?;
+
+    response::ok(EncodableOrganizationMember::from(member))
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/orgs/:id/members/:user_id", handler = "remove_organization_member")]
+pub(super) struct RemoveOrganizationMember;
+
+fn remove_organization_member(req: &mut Request, id: i32, user_id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let org = find_organization(&conn, id)?;
+    check_role(req, &conn, &org, Role::Maintainer)?;
+
+    let member = OrganizationMember::find(&conn, org.id, user_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The member does not exist"))?;
+    if member.role() == Role::Owner {
+        check_role(req, &conn, &org, Role::Owner)?;
+    }
+
+    member.remove(&conn).map_err(error::server_error)?;
+
+    response::no_content()
+}
+
+
+
+#[derive(Serialize)]
+pub struct EncodableOrganization {
+    id: i32,
+    created_at: String,
+    name: String,
+    display_name: Option<String>,
+    description: Option<String>,
+}
+
+impl From<Organization> for EncodableOrganization {
+    fn from(val: Organization) -> Self {
+        EncodableOrganization {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            name: val.name,
+            display_name: val.display_name,
+            description: val.description,
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct EncodableOrganizationMember {
+    id: i32,
+    created_at: String,
+    organization_id: i32,
+    user_id: i32,
+    role: String,
+}
+
+impl From<OrganizationMember> for EncodableOrganizationMember {
+    fn from(val: OrganizationMember) -> Self {
+        EncodableOrganizationMember {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            organization_id: val.organization_id,
+            user_id: val.user_id,
+            role: val.role,
+        }
+    }
+}
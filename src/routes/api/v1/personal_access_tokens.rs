@@ -0,0 +1,117 @@
+use bodyparser::Struct;
+use chrono::{NaiveDate, NaiveDateTime};
+use iron::prelude::*;
+
+use db::DB;
+use models::PersonalAccessToken;
+use super::{response, error};
+use super::auth::require_user;
+
+
+#[derive(Route)]
+#[get(path = "/personal_access_tokens", handler = "get_tokens")]
+pub(super) struct GetTokens;
+
+fn get_tokens(req: &mut Request) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user_id = require_user(req)?.id;
+
+    let tokens: Vec<EncodableToken> = PersonalAccessToken::load_by_user(&conn, user_id)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(tokens)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/personal_access_tokens", handler = "create_token")]
+pub(super) struct CreateToken;
+
+fn create_token(req: &mut Request) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        name: String,
+        scopes: Vec<String>,
+        /// The expiration date, in the form of `YYYY-MM-DD`.
+        expires_at: Option<String>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let expires_at: Option<NaiveDateTime> = match params.expires_at {
+        Some(ref s) => {
+            let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| {
+                error::bad_request("`expires_at` should be in the form of YYYY-MM-DD")
+            })?;
+            Some(date.and_hms(0, 0, 0))
+        }
+        None => None,
+    };
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user_id = require_user(req)?.id;
+
+    let (token, secret) = PersonalAccessToken::create(&conn, user_id, &params.name, &params.scopes, expires_at)
+        .map_err(|err| error::bad_request(&err.to_string()))?;
+
+    let mut token = EncodableToken::from(token);
+    token.token = Some(secret);
+    response::created(token)
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/personal_access_tokens/:id", handler = "revoke_token")]
+pub(super) struct RevokeToken;
+
+fn revoke_token(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user_id = require_user(req)?.id;
+
+    let token = PersonalAccessToken::find_by_id(&conn, id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The token does not exist"))?;
+    if token.user_id != user_id {
+        return Err(error::not_found("The token does not exist"));
+    }
+
+    token.revoke(&conn).map_err(error::server_error)?;
+
+    response::no_content()
+}
+
+
+
+#[derive(Serialize)]
+pub struct EncodableToken {
+    id: i32,
+    created_at: String,
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<String>,
+    revoked: bool,
+    /// The raw secret, which is only returned once on creation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+impl From<PersonalAccessToken> for EncodableToken {
+    fn from(val: PersonalAccessToken) -> Self {
+        EncodableToken {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            name: val.name,
+            scopes: val.scopes,
+            expires_at: val.expires_at.map(|t| t.format("%Y-%m-%d").to_string()),
+            revoked: val.revoked,
+            token: None,
+        }
+    }
+}
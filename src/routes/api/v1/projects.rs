@@ -4,10 +4,42 @@ use iron::prelude::*;
 use iron::status;
 use bodyparser::Struct;
 
-use models::{Project, NewProject};
+use diesel::pg::PgConnection;
+use models::{User, Project, NewProject, Organization, OrganizationMember, LfsObject};
+use models::project_members::Role;
+use models::projects::AccessLevel;
+use models::webhooks::{EVENT_PROJECT_CREATED, EVENT_PROJECT_DELETED};
 
+use config::Config;
 use db::DB;
+use lfs;
+use webhooks;
 use super::{response, error};
+use super::auth::require_user;
+
+
+/// Finds the project and checks whether the current user has the required access level to it.
+///
+/// Projects which the user is not allowed to read are reported as not found.
+pub(super) fn find_project(req: &Request, conn: &PgConnection, id: i32, required: AccessLevel) -> IronResult<Project> {
+    let user_id = req.extensions.get::<User>().map(|user| user.id);
+    let project = Project::find_by_id(conn, id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The project does not exist"))?;
+
+    let access_level = project.access_level(conn, user_id).map_err(error::server_error)?;
+    if access_level == AccessLevel::None {
+        return Err(error::not_found("The project does not exist"));
+    }
+    if access_level < required {
+        return Err(match user_id {
+            Some(_) => error::forbidden("Insufficient permission to the project"),
+            None => error::unauthorized("Authentication is required"),
+        });
+    }
+
+    Ok(project)
+}
 
 
 
@@ -17,10 +49,9 @@ pub(super) struct GetProjects;
 
 fn get_projects(req: &mut Request) -> IronResult<Response> {
     let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user_id = req.extensions.get::<User>().map(|user| user.id);
 
-    use schema::projects;
-    let repos: Vec<EncodableProject> = projects::table
-        .load::<Project>(&*conn)
+    let repos: Vec<EncodableProject> = Project::load_readable(&conn, user_id)
         .map_err(error::server_error)?
         .into_iter()
         .map(Into::into)
@@ -38,14 +69,50 @@ pub(super) struct GetProject;
 fn get_project(req: &mut Request, id: i32) -> IronResult<Response> {
     let conn = DB::from_req(req).map_err(error::server_error)?;
 
-    use schema::projects;
-    let repo: EncodableProject = projects::table
-        .filter(projects::dsl::id.eq(id))
-        .get_result::<Project>(&*conn)
-        .map_err(error::server_error)?
-        .into();
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    let default_branch = repo.default_branch().map_err(error::server_error)?;
+    let lfs_size = LfsObject::total_size(&conn, project.id).map_err(error::server_error)?;
 
-    response::ok(repo)
+    let mut project = EncodableProject::from(project);
+    project.default_branch = default_branch;
+    project.lfs_size = Some(lfs_size);
+    response::ok(project)
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/projects/:id", handler = "update_project")]
+pub(super) struct UpdateProject;
+
+fn update_project(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        default_branch: Option<String>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+
+    if let Some(ref branch) = params.default_branch {
+        if repo.get_branch(branch).map_err(error::server_error)?.is_none() {
+            return Err(error::bad_request("The branch does not exist"));
+        }
+        repo.set_default_branch(branch).map_err(error::server_error)?;
+    }
+    let default_branch = repo.default_branch().map_err(error::server_error)?;
+    let lfs_size = LfsObject::total_size(&conn, project.id).map_err(error::server_error)?;
+
+    let mut project = EncodableProject::from(project);
+    project.default_branch = default_branch;
+    project.lfs_size = Some(lfs_size);
+    response::ok(project)
 }
 
 
@@ -60,7 +127,31 @@ fn create_project(req: &mut Request) -> IronResult<Response> {
         .ok_or_else(|| error::bad_request(""))?;
 
     let conn = DB::from_req(req).map_err(error::server_error)?;
-    let project = new_project.insert(&conn).map_err(error::server_error)?;
+    let user = require_user(req)?;
+    if new_project.user != user.name {
+        return Err(error::forbidden("Cannot create a project owned by another user"));
+    }
+    if let Some(ref organization) = new_project.organization {
+        let org = Organization::find_by_name(&conn, organization)
+            .map_err(error::server_error)?
+            .ok_or_else(|| error::bad_request("The organization does not exist"))?;
+        let role = OrganizationMember::find(&conn, org.id, user.id)
+            .map_err(error::server_error)?
+            .map(|m| m.role());
+        if role < Some(Role::Developer) {
+            return Err(error::forbidden("Insufficient permission to create a project in the organization"));
+        }
+    }
+
+    let hook_path = req.extensions.get::<Config>().unwrap().hook_path().map_err(error::server_error)?;
+    let project = new_project.insert(&conn, &hook_path).map_err(error::server_error)?;
+
+    let payload = json!({
+        "project": webhooks::project_payload(&conn, &project).map_err(error::server_error)?,
+        "user": webhooks::user_payload(user),
+    });
+    webhooks::trigger(&conn, project.id, EVENT_PROJECT_CREATED, payload)
+        .map_err(error::server_error)?;
 
     response::created(EncodableProject::from(project))
 }
@@ -72,17 +163,33 @@ fn create_project(req: &mut Request) -> IronResult<Response> {
 pub(super) struct DeleteProject;
 
 fn delete_project(req: &mut Request, id: i32) -> IronResult<Response> {
+    let root = req.extensions.get::<Config>().unwrap().repository_root.clone();
     let conn = DB::from_req(req).map_err(error::server_error)?;
 
     let project: Project = match Project::find_by_id(&conn, id).map_err(error::server_error)? {
         Some(p) => p,
         None => return Ok(Response::with(status::Ok)),
     };
+    let user = require_user(req)?;
+    let role = project.role_of(&conn, user.id).map_err(error::server_error)?;
+    if role != Some(Role::Owner) {
+        return Err(error::forbidden("Only the owner can delete the project"));
+    }
+
+    // The deliveries outlive the webhooks, which are removed together with the project.
+    let payload = json!({
+        "project": webhooks::project_payload(&conn, &project).map_err(error::server_error)?,
+        "user": webhooks::user_payload(user),
+    });
+    webhooks::trigger(&conn, project.id, EVENT_PROJECT_DELETED, payload)
+        .map_err(error::server_error)?;
+
     let repo = project.open_repository(&*conn).map_err(error::server_error)?;
 
     repo.remove().map_err(|(_, err)| {
         IronError::new(err, status::InternalServerError)
     })?;
+    lfs::remove_project(&root, project.id).map_err(error::server_error)?;
 
     delete(::schema::projects::table.filter(
         ::schema::projects::dsl::id.eq(id),
@@ -101,6 +208,13 @@ pub struct EncodableProject {
     pub user_id: i32,
     pub name: String,
     pub description: Option<String>,
+    pub visibility: String,
+    pub organization_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+    /// The total size of the LFS objects, in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lfs_size: Option<i64>,
 }
 
 impl From<Project> for EncodableProject {
@@ -111,6 +225,10 @@ impl From<Project> for EncodableProject {
             user_id: val.user_id,
             name: val.name,
             description: val.description,
+            visibility: val.visibility,
+            organization_id: val.organization_id,
+            default_branch: None,
+            lfs_size: None,
         }
     }
 }
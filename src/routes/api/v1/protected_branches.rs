@@ -0,0 +1,147 @@
+use bodyparser::Struct;
+use iron::prelude::*;
+
+use db::DB;
+use models::{ProtectedBranch, NewProtectedBranch};
+use models::project_members::Role;
+use models::projects::AccessLevel;
+use models::protected_branches::ProtectedBranchChangeset;
+use super::{response, error};
+use super::projects::find_project;
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/protected_branches", handler = "get_protected_branches")]
+pub(super) struct GetProtectedBranches;
+
+fn get_protected_branches(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+
+    let branches: Vec<EncodableProtectedBranch> = ProtectedBranch::load_by_project(&conn, project.id)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(branches)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/protected_branches", handler = "create_protected_branch")]
+pub(super) struct CreateProtectedBranch;
+
+fn create_protected_branch(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        pattern: String,
+        push_role: Option<Role>,
+        allow_force_push: Option<bool>,
+        allow_deletion: Option<bool>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+    if params.pattern.is_empty() {
+        return Err(error::bad_request("`pattern` must not be empty"));
+    }
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let new_branch = NewProtectedBranch {
+        project_id: project.id,
+        pattern: params.pattern,
+        push_role: params.push_role.unwrap_or(Role::Maintainer).as_str().to_owned(),
+        allow_force_push: params.allow_force_push.unwrap_or(false),
+        allow_deletion: params.allow_deletion.unwrap_or(false),
+    };
+    let branch = new_branch.insert(&conn).map_err(error::server_error)?;
+
+    response::created(EncodableProtectedBranch::from(branch))
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/projects/:id/protected_branches/:branch_id", handler = "update_protected_branch")]
+pub(super) struct UpdateProtectedBranch;
+
+fn update_protected_branch(req: &mut Request, id: i32, branch_id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        push_role: Option<Role>,
+        allow_force_push: Option<bool>,
+        allow_deletion: Option<bool>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let branch = ProtectedBranch::find(&conn, project.id, branch_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The protected branch does not exist"))?;
+
+    let changeset = ProtectedBranchChangeset {
+        push_role: params.push_role.map(|role| role.as_str().to_owned()),
+        allow_force_push: params.allow_force_push,
+        allow_deletion: params.allow_deletion,
+    };
+    let branch = branch.update(&conn, &changeset).map_err(
+        error::server_error,
+    )?;
+
+    response::ok(EncodableProtectedBranch::from(branch))
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/projects/:id/protected_branches/:branch_id", handler = "delete_protected_branch")]
+pub(super) struct DeleteProtectedBranch;
+
+fn delete_protected_branch(req: &mut Request, id: i32, branch_id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let branch = ProtectedBranch::find(&conn, project.id, branch_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The protected branch does not exist"))?;
+    branch.remove(&conn).map_err(error::server_error)?;
+
+    response::no_content()
+}
+
+
+
+#[derive(Serialize)]
+pub struct EncodableProtectedBranch {
+    id: i32,
+    created_at: String,
+    project_id: i32,
+    pattern: String,
+    push_role: String,
+    allow_force_push: bool,
+    allow_deletion: bool,
+}
+
+impl From<ProtectedBranch> for EncodableProtectedBranch {
+    fn from(val: ProtectedBranch) -> Self {
+        EncodableProtectedBranch {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            project_id: val.project_id,
+            pattern: val.pattern,
+            push_role: val.push_role,
+            allow_force_push: val.allow_force_push,
+            allow_deletion: val.allow_deletion,
+        }
+    }
+}
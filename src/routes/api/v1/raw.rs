@@ -0,0 +1,144 @@
+//! Responses of raw contents of blobs.
+
+use std::path::Path;
+use iron::prelude::*;
+use iron::headers::{AcceptRanges, ByteRangeSpec, ContentLength, ContentRange, ContentRangeSpec, ContentType, ETag,
+                    EntityTag, IfNoneMatch, Range, RangeUnit};
+use iron::mime::Mime;
+use iron::modifiers::Header;
+use iron::status;
+
+use models::repository::is_binary;
+
+
+header! {
+    (XContentTypeOptions, "X-Content-Type-Options") => [String]
+}
+
+
+/// Returns the raw content of a blob, supporting conditional requests keyed on the blob id and
+/// single byte range requests.
+///
+/// `blob_id` must be the full id of the blob, not a revision which resolves to it. `path` is used to
+/// guess the content type, together with the content itself.
+pub(super) fn raw_blob_response(req: &Request, blob_id: &str, path: Option<&str>, content: Vec<u8>) -> IronResult<Response> {
+    let etag = EntityTag::strong(blob_id.to_owned());
+    let content_type = ContentType(guess_content_type(path, &content));
+
+    let not_modified = match req.headers.get::<IfNoneMatch>() {
+        Some(&IfNoneMatch::Any) => true,
+        Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+    if not_modified {
+        return Ok(Response::with((status::NotModified, Header(ETag(etag)))));
+    }
+
+    let len = content.len() as u64;
+    let range = match req.headers.get::<Range>() {
+        Some(&Range::Bytes(ref specs)) if specs.len() == 1 => Some(resolve_range(&specs[0], len)),
+        _ => None,
+    };
+
+    let mut response = match range {
+        Some(Some((start, end))) => {
+            let body = content[start as usize..end as usize + 1].to_vec();
+            Response::with((
+                status::PartialContent,
+                Header(ContentRange(ContentRangeSpec::Bytes {
+                    range: Some((start, end)),
+                    instance_length: Some(len),
+                })),
+                body,
+            ))
+        }
+        Some(None) => {
+            return Ok(Response::with((
+                status::RangeNotSatisfiable,
+                Header(ContentRange(ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(len),
+                })),
+            )))
+        }
+        None => Response::with((status::Ok, Header(ContentLength(len)), content)),
+    };
+    response.headers.set(content_type);
+    response.headers.set(ETag(etag));
+    response.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+    response.headers.set(XContentTypeOptions("nosniff".to_owned()));
+    Ok(response)
+}
+
+/// Resolves the range into the first and last positions, or `None` if it is not satisfiable.
+fn resolve_range(spec: &ByteRangeSpec, len: u64) -> Option<(u64, u64)> {
+    if len == 0 {
+        return None;
+    }
+    match *spec {
+        ByteRangeSpec::FromTo(start, end) if start < len && start <= end => Some((start, end.min(len - 1))),
+        ByteRangeSpec::AllFrom(start) if start < len => Some((start, len - 1)),
+        ByteRangeSpec::Last(n) if n > 0 => Some((len - n.min(len), len - 1)),
+        _ => None,
+    }
+}
+
+
+/// Guesses the content type from the magic bytes of the content, or from the extension of the path.
+///
+/// Types which browsers may render as active content (e.g. HTML, SVG and JavaScript) are served as
+/// plain text, so that raw files of repositories cannot be used to run scripts on this origin.
+pub(super) fn guess_content_type(path: Option<&str>, content: &[u8]) -> Mime {
+    let content_type = guess_from_magic(content)
+        .or_else(|| {
+            path.and_then(|path| Path::new(path).extension())
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| guess_from_extension(&ext.to_lowercase()))
+        })
+        .unwrap_or_else(|| if is_binary(content) {
+            "application/octet-stream"
+        } else {
+            "text/plain; charset=utf-8"
+        });
+    content_type.parse().unwrap()
+}
+
+fn guess_from_magic(content: &[u8]) -> Option<&'static str> {
+    const MAGICS: &'static [(&'static [u8], &'static str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+    if content.len() >= 12 && &content[0..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    MAGICS
+        .iter()
+        .find(|&&(magic, _)| content.starts_with(magic))
+        .map(|&(_, content_type)| content_type)
+}
+
+fn guess_from_extension(ext: &str) -> Option<&'static str> {
+    let content_type = match ext {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => return None,
+    };
+    Some(content_type)
+}
@@ -1,24 +1,99 @@
+use bodyparser::Struct;
 use iron::prelude::*;
 use iron::status;
 use std::borrow::Borrow;
 use iron::headers::ContentType;
 use iron::modifiers::Header;
 use base64;
+use chrono::{DateTime, NaiveDate};
 use url::Url;
 
+use diesel::pg::PgConnection;
+use iron::response::WriteBody;
+use std::io::{self, Write};
+
+use config::Config;
 use db::DB;
-use models::{Project, Repository};
+use error::{AppError, AppErrorKind};
+use hooks;
+use models::{Project, Repository, User};
+use models::protected_branches::ProtectedBranch;
+use models::repository::{is_binary, ArchiveFormat, ArchiveTree, CommitFilter, DiffOptions, FileAction, FileActionKind, Signature,
+                         MAX_DIFF_SIZE};
+use models::projects::AccessLevel;
 use super::{response, error};
+use super::auth::require_user;
+use super::files::decode_content;
+use super::projects::find_project;
+use super::raw::raw_blob_response;
 
 
 fn open_repository_from_id(req: &mut Request, id: i32) -> IronResult<Repository> {
     let conn = DB::from_req(req).map_err(error::server_error)?;
-    let project = Project::find_by_id(&conn, id)
-        .map_err(error::server_error)?
-        .ok_or_else(|| error::not_found(""))?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
     project.open_repository(&*conn).map_err(error::server_error)
 }
 
+/// Converts the error from the repository into the response, according to its kind.
+pub(super) fn repository_error(err: AppError) -> IronError {
+    match *err.kind() {
+        AppErrorKind::NotFound(ref message) => error::not_found(message),
+        AppErrorKind::Conflict(ref message) => error::conflict(message),
+        AppErrorKind::Invalid(ref message) => error::bad_request(message),
+        _ => error::server_error(err),
+    }
+}
+
+/// Returns the email address recorded into objects created by the user.
+///
+/// Users do not have email addresses registered, so a placeholder derived from the name is used.
+pub(super) fn email_of(user: &User) -> String {
+    format!("{}@users.noreply.gallium", user.name)
+}
+
+pub(super) fn signature_of<'a>(user: &'a User, email: &'a str) -> Signature<'a> {
+    Signature {
+        name: user.screen_name.as_ref().map(|s| s.as_str()).unwrap_or(&user.name),
+        email: email,
+    }
+}
+
+/// Checks whether the user is allowed to update the branch under the protected branch rules.
+pub(super) fn check_protected_branch(
+    conn: &PgConnection,
+    project: &Project,
+    user: &User,
+    branch: &str,
+    is_deletion: bool,
+) -> IronResult<()> {
+    let rule = match ProtectedBranch::find_matching(conn, project.id, branch).map_err(error::server_error)? {
+        Some(rule) => rule,
+        None => return Ok(()),
+    };
+    let role = project.role_of(conn, user.id).map_err(error::server_error)?;
+    rule.check_update(branch, role, is_deletion, false).map_err(
+        |message| error::forbidden(&message),
+    )
+}
+
+/// Notifies an update of a reference made through the API, in the same way as pushes.
+///
+/// The reference has already been updated, so failures are reported but not fatal.
+pub(super) fn notify_ref_update(
+    conn: &PgConnection,
+    project: &Project,
+    user: &User,
+    repo: &Repository,
+    refname: &str,
+    old: &str,
+    new: &str,
+) {
+    let updates = [(refname.to_owned(), old.to_owned(), new.to_owned())];
+    if let Err(err) = hooks::post_receive(conn, project, Some(user.id), repo, &updates) {
+        let _ = writeln!(&mut ::std::io::stderr(), "Failed to trigger webhooks: {}", err);
+    }
+}
+
 
 #[derive(Route)]
 #[get(path = "/projects/:id/repository/tree", handler = "show_tree")]
@@ -41,7 +116,7 @@ fn show_tree(req: &mut Request, id: i32) -> IronResult<Response> {
 
     let repo = open_repository_from_id(req, id)?;
     let tree = repo.list_tree(refname, path, recursive).map_err(
-        error::server_error,
+        repository_error,
     )?;
     response::ok(tree)
 }
@@ -52,17 +127,23 @@ fn show_tree(req: &mut Request, id: i32) -> IronResult<Response> {
 pub(super) struct GetBlob;
 
 fn get_blob(req: &mut Request, id: i32, sha: String) -> IronResult<Response> {
+    let max_size = req.extensions.get::<Config>().unwrap().max_inline_blob_size;
+
     let repo = open_repository_from_id(req, id)?;
-    let content = repo.get_blob_content(&sha)
-        .map_err(error::server_error)?
-        .map(|content| base64::encode(&content))
-        .ok_or_else(|| error::not_found(""))?;
+    let (sha, content) = repo.get_blob_content(&sha)
+        .map_err(repository_error)?;
+    if content.len() > max_size {
+        return Err(error::payload_too_large(
+            &format!("The blob is larger than {} bytes, use the raw endpoint instead", max_size),
+        ));
+    }
 
     response::ok(json!({
         "sha": sha,
         "encoding": "base64",
-        "content": content,
+        "binary": is_binary(&content),
         "size": content.len(),
+        "content": base64::encode(&content),
     }))
 }
 
@@ -73,10 +154,328 @@ pub(super) struct GetRawBlob;
 
 fn get_raw_blob(req: &mut Request, id: i32, sha: String) -> IronResult<Response> {
     let repo = open_repository_from_id(req, id)?;
-    let content = repo.get_blob_content(&sha)
-        .map_err(error::server_error)?
-        .ok_or_else(|| error::not_found(""))?;
-    Ok(Response::with(
-        (status::Ok, Header(ContentType::plaintext()), content),
-    ))
+    // The revision may be abbreviated, and the ETag has to be the full id of the blob.
+    let (blob_id, content) = repo.get_blob_content(&sha)
+        .map_err(repository_error)?;
+    raw_blob_response(req, &blob_id, None, content)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/commits", handler = "get_commits")]
+pub(super) struct GetCommits;
+
+fn get_commits(req: &mut Request, id: i32) -> IronResult<Response> {
+    let (mut refname, mut path, mut author) = (None, None, None);
+    let (mut since, mut until, mut page, mut per_page) = (None, None, None, None);
+    let url: Url = req.url.clone().into();
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "ref" => refname = Some(val.into_owned()),
+            "path" => path = Some(val.into_owned()),
+            "author" => author = Some(val.into_owned()),
+            "since" => since = Some(parse_time(&val, false).ok_or_else(|| error::bad_request("invalid `since`"))?),
+            "until" => until = Some(parse_time(&val, true).ok_or_else(|| error::bad_request("invalid `until`"))?),
+            "page" => page = val.parse().ok(),
+            "per_page" => per_page = val.parse().ok(),
+            _ => (),
+        }
+    }
+    let refname = refname.as_ref().map(|s| s.as_str()).unwrap_or("HEAD");
+    let page: usize = page.unwrap_or(1).max(1);
+    let per_page: usize = per_page.unwrap_or(20).max(1).min(100);
+
+    let filter = CommitFilter {
+        path: path.as_ref().map(|s| s.as_str()),
+        since: since,
+        until: until,
+        author: author.as_ref().map(|s| s.as_str()),
+    };
+
+    let repo = open_repository_from_id(req, id)?;
+    let commits = repo.list_commits(refname, &filter, (page - 1) * per_page, per_page)
+        .map_err(repository_error)?;
+    response::ok(commits)
+}
+
+/// Parses the time given in either RFC 3339 or `YYYY-MM-DD`, into UNIX timestamp.
+///
+/// A date without the time is interpreted as the start of the day,
+/// or as the end of the day if `end_of_day` is true.
+fn parse_time(s: &str, end_of_day: bool) -> Option<i64> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+        return Some(datetime.timestamp());
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").ok().map(|date| if end_of_day {
+        date.and_hms(23, 59, 59).timestamp()
+    } else {
+        date.and_hms(0, 0, 0).timestamp()
+    })
+}
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/repository/commits", handler = "create_commit")]
+pub(super) struct CreateCommit;
+
+fn create_commit(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct ActionParams {
+        action: String,
+        file_path: String,
+        previous_path: Option<String>,
+        content: Option<String>,
+        encoding: Option<String>,
+        last_commit_id: Option<String>,
+        execute_filemode: Option<bool>,
+    }
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        branch: String,
+        start_ref: Option<String>,
+        commit_message: String,
+        actions: Vec<ActionParams>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+    if params.actions.is_empty() {
+        return Err(error::bad_request("`actions` must not be empty"));
+    }
+
+    let mut actions = Vec::with_capacity(params.actions.len());
+    for action in params.actions {
+        let content = match action.content {
+            Some(content) => Some(decode_content(content, action.encoding.as_ref().map(|s| s.as_str()))?),
+            None => None,
+        };
+        let kind = match action.action.as_str() {
+            "create" => FileActionKind::Create(content.ok_or_else(|| error::bad_request("`content` is required to create a file"))?),
+            "update" => FileActionKind::Update(content.ok_or_else(|| error::bad_request("`content` is required to update a file"))?),
+            "delete" => FileActionKind::Delete,
+            "move" => FileActionKind::Move {
+                previous_path: action.previous_path.ok_or_else(|| error::bad_request("`previous_path` is required to move a file"))?,
+                content: content,
+            },
+            "chmod" => FileActionKind::Chmod(action.execute_filemode.ok_or_else(|| error::bad_request("`execute_filemode` is required to chmod a file"))?),
+            _ => return Err(error::bad_request(&format!("Unknown action: {}", action.action))),
+        };
+        actions.push(FileAction {
+            path: action.file_path,
+            kind: kind,
+            last_commit_id: action.last_commit_id,
+        });
+    }
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Write)?;
+    let user = require_user(req)?;
+    check_protected_branch(&conn, &project, user, &params.branch, false)?;
+
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    let email = email_of(user);
+    let (old, new) = repo.commit_files(
+        &params.branch,
+        params.start_ref.as_ref().map(|s| s.as_str()),
+        &actions,
+        &params.commit_message,
+        &signature_of(user, &email),
+    ).map_err(repository_error)?;
+
+    let refname = format!("refs/heads/{}", params.branch);
+    let old = old.as_ref().map(|s| s.as_str()).unwrap_or(hooks::ZERO_OID);
+    notify_ref_update(&conn, &project, user, &repo, &refname, old, &new);
+
+    let commit = repo.get_commit(&new).map_err(error::server_error)?;
+    response::created(commit)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/commits/:sha", handler = "get_commit")]
+pub(super) struct GetCommit;
+
+fn get_commit(req: &mut Request, id: i32, sha: String) -> IronResult<Response> {
+    let repo = open_repository_from_id(req, id)?;
+    let commit = repo.get_commit(&sha)
+        .map_err(repository_error)?;
+    response::ok(commit)
+}
+
+
+/// Extracts the options of diffs from the query parameters `context_lines`, `ignore_whitespace` and `max_size`.
+fn diff_options(url: &Url) -> IronResult<DiffOptions> {
+    let mut options = DiffOptions::default();
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "context_lines" => {
+                options.context_lines = val.parse().map_err(|_| error::bad_request("invalid `context_lines`"))?
+            }
+            "ignore_whitespace" => {
+                options.ignore_whitespace = val.parse().map_err(|_| error::bad_request("invalid `ignore_whitespace`"))?
+            }
+            "max_size" => {
+                let max_size: usize = val.parse().map_err(|_| error::bad_request("invalid `max_size`"))?;
+                options.max_size = max_size.min(MAX_DIFF_SIZE);
+            }
+            _ => (),
+        }
+    }
+    Ok(options)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/commits/:sha/diff", handler = "get_commit_diff")]
+pub(super) struct GetCommitDiff;
+
+fn get_commit_diff(req: &mut Request, id: i32, sha: String) -> IronResult<Response> {
+    let url: Url = req.url.clone().into();
+    let options = diff_options(&url)?;
+
+    let repo = open_repository_from_id(req, id)?;
+    let diff = repo.diff_commit(&sha, &options)
+        .map_err(repository_error)?;
+    response::ok(diff)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/compare", handler = "compare")]
+pub(super) struct Compare;
+
+fn compare(req: &mut Request, id: i32) -> IronResult<Response> {
+    let (mut from, mut to) = (None, None);
+    let url: Url = req.url.clone().into();
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "from" => from = Some(val.into_owned()),
+            "to" => to = Some(val.into_owned()),
+            _ => (),
+        }
+    }
+    let from = from.ok_or_else(|| error::bad_request("`from` is required"))?;
+    let to = to.ok_or_else(|| error::bad_request("`to` is required"))?;
+    let options = diff_options(&url)?;
+
+    let repo = open_repository_from_id(req, id)?;
+    let diff = repo.compare(&from, &to, &options)
+        .map_err(repository_error)?;
+    response::ok(diff)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/blame", handler = "get_blame")]
+pub(super) struct GetBlame;
+
+fn get_blame(req: &mut Request, id: i32) -> IronResult<Response> {
+    let (mut refname, mut path) = (None, None);
+    let url: Url = req.url.clone().into();
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "ref" => refname = Some(val.into_owned()),
+            "path" => path = Some(val.into_owned()),
+            _ => (),
+        }
+    }
+    let refname = refname.as_ref().map(|s| s.as_str()).unwrap_or("HEAD");
+    let path = path.ok_or_else(|| error::bad_request("`path` is required"))?;
+
+    let repo = open_repository_from_id(req, id)?;
+    let blame = repo.blame(refname, &path)
+        .map_err(repository_error)?;
+    response::ok(blame)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/archive.tar.gz", handler = "get_archive_tar_gz")]
+pub(super) struct GetArchiveTarGz;
+
+fn get_archive_tar_gz(req: &mut Request, id: i32) -> IronResult<Response> {
+    get_archive(req, id, ArchiveFormat::TarGz)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/archive.tar", handler = "get_archive_tar")]
+pub(super) struct GetArchiveTar;
+
+fn get_archive_tar(req: &mut Request, id: i32) -> IronResult<Response> {
+    get_archive(req, id, ArchiveFormat::Tar)
+}
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/archive.zip", handler = "get_archive_zip")]
+pub(super) struct GetArchiveZip;
+
+fn get_archive_zip(req: &mut Request, id: i32) -> IronResult<Response> {
+    get_archive(req, id, ArchiveFormat::Zip)
+}
+
+fn get_archive(req: &mut Request, id: i32, format: ArchiveFormat) -> IronResult<Response> {
+    let (mut refname, mut path) = (None, None);
+    let url: Url = req.url.clone().into();
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "ref" => refname = Some(val.into_owned()),
+            "path" => path = Some(val.into_owned()),
+            _ => (),
+        }
+    }
+    let refname = refname.unwrap_or_else(|| "HEAD".to_owned());
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+
+    let tree = repo.find_archive_tree(&refname, path.as_ref().map(|s| s.as_str()))
+        .map_err(repository_error)?;
+
+    // The name is put into the header as is, and must not contain quotes or line breaks.
+    let basename: String = format!("{}-{}", project.name, refname)
+        .chars()
+        .map(|c| match c {
+            'A'...'Z' | 'a'...'z' | '0'...'9' | '.' | '_' | '-' => c,
+            _ => '-',
+        })
+        .collect();
+    let content_type = format.content_type().parse().map_err(|_| {
+        error::server_error(AppError::from("invalid content type"))
+    })?;
+
+    let mut response = Response::with((status::Ok, Header(ContentType(content_type))));
+    response.headers.set_raw(
+        "Content-Disposition",
+        vec![
+            format!("attachment; filename=\"{}.{}\"", basename, format.extension())
+                .into_bytes(),
+        ],
+    );
+    response.body = Some(Box::new(ArchiveBody {
+        repo: repo,
+        tree: tree,
+        prefix: format!("{}/", basename),
+        format: format,
+    }));
+    Ok(response)
+}
+
+/// The body of responses which writes the archive while being sent.
+struct ArchiveBody {
+    repo: Repository,
+    tree: ArchiveTree,
+    prefix: String,
+    format: ArchiveFormat,
+}
+
+impl WriteBody for ArchiveBody {
+    fn write_body(&mut self, res: &mut Write) -> io::Result<()> {
+        self.repo
+            .write_archive(&self.tree, &self.prefix, self.format, res)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))
+    }
 }
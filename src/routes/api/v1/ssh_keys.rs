@@ -1,12 +1,15 @@
 use diesel::{insert, delete};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use diesel::prelude::*;
 use iron::prelude::*;
 use bodyparser::Struct;
 
 use models::{SshKey, NewSshKey};
+use models::ssh_keys::PublicKey;
 use schema::ssh_keys;
 use db::DB;
 use super::{response, error};
+use super::auth::require_user;
 
 
 #[derive(Route)]
@@ -36,7 +39,9 @@ fn get_ssh_key(req: &mut Request, id: i32) -> IronResult<Response> {
     let key: EncodablePublicKey = ssh_keys::table
         .filter(ssh_keys::dsl::id.eq(id))
         .get_result::<SshKey>(&*conn)
+        .optional()
         .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The key does not exist"))?
         .into();
 
     response::ok(key)
@@ -49,17 +54,37 @@ fn get_ssh_key(req: &mut Request, id: i32) -> IronResult<Response> {
 pub(super) struct AddKey;
 
 fn add_ssh_key(req: &mut Request) -> IronResult<Response> {
-    let new_key = req.get::<Struct<NewSshKey>>()
+    let mut new_key = req.get::<Struct<NewSshKey>>()
         .ok()
         .and_then(|s| s)
         .ok_or_else(|| error::bad_request(""))?;
+    new_key.normalize().map_err(|err| error::bad_request(&err))?;
 
     let conn = DB::from_req(req).map_err(error::server_error)?;
+    if new_key.user_id != require_user(req)?.id {
+        return Err(error::forbidden("Cannot register a key for another user"));
+    }
+
+    // A key must not be shared between users, since the user is identified by the key on SSH access.
+    let fingerprint = new_key.fingerprint.clone().unwrap();
+    if SshKey::find_by_fingerprint(&*conn, &fingerprint)
+        .map_err(error::server_error)?
+        .is_some()
+    {
+        return Err(error::conflict("The key is already registered"));
+    }
+
     let key: EncodablePublicKey = insert(&new_key)
         .into(ssh_keys::table)
         .get_result::<SshKey>(&*conn)
         .map(Into::into)
-        .map_err(error::server_error)?;
+        .map_err(|err| match err {
+            // Another request may have registered the same key after the check above.
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                error::conflict("The key is already registered")
+            }
+            err => error::server_error(err),
+        })?;
 
     response::created(key)
 }
@@ -72,6 +97,17 @@ pub(super) struct DeleteKey;
 
 fn delete_ssh_key(req: &mut Request, id: i32) -> IronResult<Response> {
     let conn = DB::from_req(req).map_err(error::server_error)?;
+    let user_id = require_user(req)?.id;
+
+    let key = ssh_keys::table
+        .filter(ssh_keys::dsl::id.eq(id))
+        .get_result::<SshKey>(&*conn)
+        .optional()
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The key does not exist"))?;
+    if key.user_id != user_id {
+        return Err(error::forbidden("Only the owner can delete the key"));
+    }
 
     delete(ssh_keys::table.filter(ssh_keys::dsl::id.eq(id)))
         .execute(&*conn)
@@ -89,16 +125,27 @@ pub struct EncodablePublicKey {
     user_id: i32,
     description: Option<String>,
     key: String,
+    fingerprint: Option<String>,
+    fingerprint_md5: Option<String>,
+    #[serde(rename = "type")]
+    key_type: Option<String>,
+    bits: Option<u32>,
 }
 
 impl From<SshKey> for EncodablePublicKey {
     fn from(val: SshKey) -> Self {
+        // Keys registered before the validation was introduced may fail to be parsed.
+        let parsed = PublicKey::parse(&val.key).ok();
         EncodablePublicKey {
             id: val.id,
             created_at: val.created_at.format("%c").to_string(),
             user_id: val.user_id,
             description: val.description,
             key: val.key,
+            fingerprint: val.fingerprint,
+            fingerprint_md5: val.fingerprint_md5,
+            key_type: parsed.as_ref().map(|key| key.key_type.clone()),
+            bits: parsed.as_ref().map(|key| key.bits),
         }
     }
 }
@@ -0,0 +1,109 @@
+use bodyparser::Struct;
+use iron::prelude::*;
+
+use db::DB;
+use hooks::ZERO_OID;
+use models::projects::AccessLevel;
+use super::{response, error};
+use super::auth::require_user;
+use super::files::decode_path;
+use super::projects::find_project;
+use super::repository::{repository_error, email_of, signature_of, notify_ref_update};
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/tags", handler = "get_tags")]
+pub(super) struct GetTags;
+
+fn get_tags(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+
+    let tags = repo.list_tags().map_err(error::server_error)?;
+    response::ok(tags)
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/repository/tags/*tag", handler = "get_tag")]
+pub(super) struct GetTag;
+
+fn get_tag(req: &mut Request, id: i32, tag: String) -> IronResult<Response> {
+    let tag = decode_path(&tag);
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Read)?;
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+
+    let tag = repo.get_tag(&tag)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The tag does not exist"))?;
+    response::ok(tag)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/repository/tags", handler = "create_tag")]
+pub(super) struct CreateTag;
+
+fn create_tag(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        tag: String,
+        #[serde(rename = "ref")]
+        revision: String,
+        message: Option<String>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Write)?;
+    let user = require_user(req)?;
+
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    if repo.get_tag(&params.tag).map_err(error::server_error)?.is_some() {
+        return Err(error::bad_request("The tag already exists"));
+    }
+    let email = email_of(user);
+    let tag = repo.create_tag(
+        &params.tag,
+        &params.revision,
+        params.message.as_ref().map(|s| s.as_str()),
+        &signature_of(user, &email),
+    ).map_err(repository_error)?;
+
+    if let Some(target) = tag["target"].as_str() {
+        let refname = format!("refs/tags/{}", params.tag);
+        notify_ref_update(&conn, &project, user, &repo, &refname, ZERO_OID, target);
+    }
+
+    response::created(tag)
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/projects/:id/repository/tags/*tag", handler = "delete_tag")]
+pub(super) struct DeleteTag;
+
+fn delete_tag(req: &mut Request, id: i32, tag: String) -> IronResult<Response> {
+    let tag = decode_path(&tag);
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Write)?;
+    let user = require_user(req)?;
+
+    let repo = project.open_repository(&*conn).map_err(error::server_error)?;
+    let old = repo.delete_tag(&tag)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The tag does not exist"))?;
+
+    let refname = format!("refs/tags/{}", tag);
+    notify_ref_update(&conn, &project, user, &repo, &refname, &old, ZERO_OID);
+
+    response::no_content()
+}
@@ -2,7 +2,7 @@ use bodyparser::Struct;
 use iron::prelude::*;
 use iron::status;
 
-use error::AppError;
+use error::{AppError, AppErrorKind};
 use db::DB;
 use models::User;
 use super::{response, error};
@@ -63,10 +63,12 @@ fn create_user(req: &mut Request) -> IronResult<Response> {
         &params.name,
         &params.password,
         params.screen_name.as_ref().map(|s| s.as_str()),
-    ).map_err(error::server_error)
-        .map(EncodableUser::from)?;
+    ).map_err(|err| match *err.kind() {
+        AppErrorKind::Invalid(ref message) => error::bad_request(message),
+        _ => error::server_error(err),
+    })?;
 
-    response::created(user)
+    response::created(EncodableUser::from(user))
 }
 
 
@@ -0,0 +1,211 @@
+use bodyparser::Struct;
+use iron::prelude::*;
+use serde::{Deserialize, Deserializer};
+
+use db::DB;
+use models::{Webhook, NewWebhook, WebhookDelivery};
+use models::projects::AccessLevel;
+use models::webhooks::WebhookChangeset;
+use super::{response, error};
+use super::projects::find_project;
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/hooks", handler = "get_webhooks")]
+pub(super) struct GetWebhooks;
+
+fn get_webhooks(req: &mut Request, id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let hooks: Vec<EncodableWebhook> = Webhook::load_by_project(&conn, project.id)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(hooks)
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/projects/:id/hooks", handler = "create_webhook")]
+pub(super) struct CreateWebhook;
+
+fn create_webhook(req: &mut Request, id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        url: String,
+        secret: Option<String>,
+        events: Vec<String>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let new_hook = NewWebhook {
+        project_id: project.id,
+        url: params.url,
+        secret: params.secret,
+        events: params.events,
+    };
+    let hook = new_hook.insert(&conn).map_err(
+        |err| error::bad_request(&err.to_string()),
+    )?;
+
+    response::created(EncodableWebhook::from(hook))
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/projects/:id/hooks/:hook_id", handler = "update_webhook")]
+pub(super) struct UpdateWebhook;
+
+fn update_webhook(req: &mut Request, id: i32, hook_id: i32) -> IronResult<Response> {
+    #[derive(Clone, Deserialize)]
+    struct Params {
+        url: Option<String>,
+        /// `null` removes the secret, while a missing field leaves it unchanged.
+        #[serde(default, deserialize_with = "deserialize_some")]
+        secret: Option<Option<String>>,
+        events: Option<Vec<String>>,
+        active: Option<bool>,
+    }
+    let params = req.get::<Struct<Params>>()
+        .ok()
+        .and_then(|s| s)
+        .ok_or_else(|| error::bad_request(""))?;
+
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let hook = Webhook::find(&conn, project.id, hook_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The webhook does not exist"))?;
+
+    let changeset = WebhookChangeset {
+        url: params.url,
+        secret: params.secret,
+        events: params.events,
+        active: params.active,
+    };
+    let hook = hook.update(&conn, &changeset).map_err(
+        |err| error::bad_request(&err.to_string()),
+    )?;
+
+    response::ok(EncodableWebhook::from(hook))
+}
+
+
+
+#[derive(Route)]
+#[delete(path = "/projects/:id/hooks/:hook_id", handler = "delete_webhook")]
+pub(super) struct DeleteWebhook;
+
+fn delete_webhook(req: &mut Request, id: i32, hook_id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let hook = Webhook::find(&conn, project.id, hook_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The webhook does not exist"))?;
+    hook.remove(&conn).map_err(error::server_error)?;
+
+    response::no_content()
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/projects/:id/hooks/:hook_id/deliveries", handler = "get_webhook_deliveries")]
+pub(super) struct GetWebhookDeliveries;
+
+fn get_webhook_deliveries(req: &mut Request, id: i32, hook_id: i32) -> IronResult<Response> {
+    let conn = DB::from_req(req).map_err(error::server_error)?;
+    let project = find_project(req, &conn, id, AccessLevel::Admin)?;
+
+    let hook = Webhook::find(&conn, project.id, hook_id)
+        .map_err(error::server_error)?
+        .ok_or_else(|| error::not_found("The webhook does not exist"))?;
+    let deliveries: Vec<EncodableDelivery> = WebhookDelivery::load_by_webhook(&conn, hook.id)
+        .map_err(error::server_error)?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    response::ok(deliveries)
+}
+
+
+
+/// Deserializes a present field into `Some`, even if it is `null`.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+
+
+#[derive(Serialize)]
+pub struct EncodableWebhook {
+    id: i32,
+    created_at: String,
+    project_id: i32,
+    url: String,
+    has_secret: bool,
+    events: Vec<String>,
+    active: bool,
+}
+
+impl From<Webhook> for EncodableWebhook {
+    fn from(val: Webhook) -> Self {
+        EncodableWebhook {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            project_id: val.project_id,
+            url: val.url,
+            has_secret: val.secret.is_some(),
+            events: val.events,
+            active: val.active,
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+pub struct EncodableDelivery {
+    id: i32,
+    created_at: String,
+    event: String,
+    url: String,
+    attempts: i32,
+    delivered_at: Option<String>,
+    status_code: Option<i32>,
+    response_body: Option<String>,
+    error: Option<String>,
+}
+
+impl From<WebhookDelivery> for EncodableDelivery {
+    fn from(val: WebhookDelivery) -> Self {
+        EncodableDelivery {
+            id: val.id,
+            created_at: val.created_at.format("%c").to_string(),
+            event: val.event,
+            url: val.url,
+            attempts: val.attempts,
+            delivered_at: val.delivered_at.map(|t| t.format("%c").to_string()),
+            status_code: val.status_code,
+            response_body: val.response_body,
+            error: val.error,
+        }
+    }
+}
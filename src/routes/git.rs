@@ -1,25 +1,49 @@
-use std::io::Read;
+use std::fs::File;
+use std::io::{self, Read, Write};
 use iron::prelude::*;
+use iron::response::WriteBody;
 use iron::status;
 use iron::headers::{Authorization, Basic, CacheControl, CacheDirective, Encoding, ContentEncoding, ContentType};
 use iron::mime::{Mime, TopLevel, SubLevel};
 use iron::modifiers::Header;
 use router::Router;
 use flate2::read::GzDecoder;
+use config::Config;
 use error::AppError;
-use models::{User, Project, Repository};
+use hooks;
+use models::{User, PersonalAccessToken, Project, Repository};
+use models::personal_access_tokens::{SCOPE_READ_REPOSITORY, SCOPE_WRITE_REPOSITORY};
+use models::projects::{AccessLevel, Visibility};
+use rpc::RpcProcess;
 use super::WWWAuthenticate;
+use super::lfs;
 use db::DB;
 use iron_router_ext::RegisterRoute;
 use url::Url;
 use std::borrow::Borrow;
 
 
+header! {
+    (GitProtocol, "Git-Protocol") => [String]
+}
+
+
 pub(super) fn create_git_router() -> Router {
     let mut router = Router::new();
     router.register(InfoRefs);
     router.register(ReceivePack);
     router.register(UploadPack);
+    router.register(DumbHead);
+    router.register(DumbInfoPacks);
+    router.register(DumbLooseObject);
+    router.register(DumbPackFile);
+    router.register(lfs::Batch);
+    router.register(lfs::Download);
+    router.register(lfs::Upload);
+    router.register(lfs::ListLocks);
+    router.register(lfs::CreateLock);
+    router.register(lfs::VerifyLocks);
+    router.register(lfs::Unlock);
     router
 }
 
@@ -59,7 +83,34 @@ fn get_basic_auth_param<'a>(req: &'a Request) -> IronResult<(&'a str, &'a str)>
     Ok((username, password))
 }
 
-fn open_repository(req: &mut Request, user: &str, project: &str) -> IronResult<(Project, Repository)> {
+/// Authenticates the user with Basic auth.
+///
+/// A personal access token which has the given scope is accepted in place of the password.
+fn authenticate(req: &mut Request, scope: &str) -> IronResult<User> {
+    let conn = DB::from_req(req).unwrap();
+    let (username, password) = get_basic_auth_param(req)?;
+
+    let token = PersonalAccessToken::authenticate(&conn, password).map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    if let Some((user, token)) = token {
+        if user.name == username {
+            if !token.has_scope(scope) {
+                return Err(IronError::new(
+                    AppError::from(""),
+                    (status::Forbidden, format!("The token does not have `{}` scope", scope)),
+                ));
+            }
+            return Ok(user);
+        }
+    }
+
+    User::authenticate(&conn, username, password)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?
+        .ok_or_else(|| IronError::new(AppError::from(""), status::Unauthorized))
+}
+
+pub(super) fn open_repository(req: &mut Request, user: &str, project: &str) -> IronResult<(Project, Repository)> {
     let conn = DB::from_req(req).unwrap();
     let (user, project) = check_repo_identifier(user, project)?;
     let project = Project::find_by_id(&conn, (user, project))
@@ -72,34 +123,39 @@ fn open_repository(req: &mut Request, user: &str, project: &str) -> IronResult<(
 
 }
 
-// TODO: check scope
-// MEMO:
-// * receive-pack
-//   - public/private ともに認証必須
-//* upload-pack
-//   - private の場合のみ認証必須
-//   - 現状は実質 public のみであるため認証回りは省略している
-fn check_scope(req: &mut Request, service: &str, project: &Project) -> IronResult<()> {
+/// Checks whether the request is allowed to run the service on the project, and returns the
+/// authenticated user if credentials were required.
+///
+/// Anonymous access is tried first so that public projects can be fetched without credentials.
+pub(super) fn check_scope(req: &mut Request, service: &str, project: &Project) -> IronResult<Option<User>> {
     let conn = DB::from_req(req).unwrap();
-    match service {
-        "receive-pack" => {
-            let (username, password) = get_basic_auth_param(req)?;
-            let auth_user = User::authenticate(&conn, username, password)
-                .map_err(|err| IronError::new(err, status::InternalServerError))?
-                .ok_or_else(|| IronError::new(AppError::from(""), status::Unauthorized))?;
-
-            if project.user_id != auth_user.id {
-                return Err(IronError::new(AppError::from(""), status::Unauthorized));
-            }
-        }
-        "upload-pack" => (),
+    let (required, scope) = match service {
+        "receive-pack" => (AccessLevel::Write, SCOPE_WRITE_REPOSITORY),
+        "upload-pack" => (AccessLevel::Read, SCOPE_READ_REPOSITORY),
         _ => unreachable!(),
+    };
+
+    let access_level = project.access_level(&conn, None).map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    if access_level >= required {
+        return Ok(None);
+    }
+
+    let auth_user = authenticate(req, scope)?;
+    let access_level = project.access_level(&conn, Some(auth_user.id)).map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    match access_level {
+        AccessLevel::None => Err(IronError::new(AppError::from(""), status::NotFound)),
+        l if l < required => Err(IronError::new(AppError::from(""), status::Unauthorized)),
+        _ => Ok(Some(auth_user)),
     }
-    Ok(())
 }
 
 
-fn get_service_name(req: &mut Request) -> IronResult<&'static str> {
+/// Returns the service requested to `info/refs`, or `None` for the dumb protocol.
+fn get_service_name(req: &mut Request) -> IronResult<Option<&'static str>> {
     let url: Url = req.url.clone().into();
     let mut service = None;
     for (key, val) in url.query_pairs() {
@@ -109,23 +165,55 @@ fn get_service_name(req: &mut Request) -> IronResult<&'static str> {
         }
     }
     match service.as_ref().map(|s| s.borrow()) {
-        Some("git-receive-pack") => Ok("receive-pack"),
-        Some("git-upload-pack") => Ok("upload-pack"),
+        Some("git-receive-pack") => Ok(Some("receive-pack")),
+        Some("git-upload-pack") => Ok(Some("upload-pack")),
         Some(ref s) => {
             Err(IronError::new(AppError::from(""), (
                 status::Forbidden,
                 format!("Invalid service name: {}", s),
             )))
         }
-        None => {
-            Err(IronError::new(AppError::from(""), (
-                status::Forbidden,
-                "Requires service name",
-            )))
-        }
+        None => Ok(None),
     }
 }
 
+/// Returns the value of `Git-Protocol` header, which is passed to git as `GIT_PROTOCOL`.
+///
+/// The value is a colon-separated list of `key=value` parameters (e.g. `version=2`). Parameters
+/// containing unexpected characters are dropped, since the value is given to the spawned process.
+fn get_git_protocol(req: &Request) -> Option<String> {
+    let value = match req.headers.get::<GitProtocol>() {
+        Some(&GitProtocol(ref value)) => value,
+        None => return None,
+    };
+    let params: Vec<&str> = value
+        .split(':')
+        .filter(|param| {
+            !param.is_empty() &&
+                param.chars().all(|c| match c {
+                    'a'...'z' | 'A'...'Z' | '0'...'9' | '=' | '-' | '_' | '.' => true,
+                    _ => false,
+                })
+        })
+        .collect();
+    if params.is_empty() {
+        None
+    } else {
+        Some(params.join(":"))
+    }
+}
+
+/// Returns whether the service speaks the protocol version 2 to the client.
+///
+/// Only `git upload-pack` supports it, and `git receive-pack` falls back to the version 0 even if
+/// the client requests the version 2.
+fn is_protocol_v2(service: &str, protocol: Option<&str>) -> bool {
+    service == "upload-pack" &&
+        protocol
+            .map(|protocol| protocol.split(':').any(|param| param == "version=2"))
+            .unwrap_or(false)
+}
+
 fn packet_write(data: &str) -> Vec<u8> {
     let s = format!("{:x}", data.len() + 4);
     if s.len() % 4 == 0 {
@@ -144,7 +232,7 @@ fn packet_write(data: &str) -> Vec<u8> {
 
 fn handle_service_rpc(req: &mut Request, user: &str, project: &str, service: &str) -> IronResult<Response> {
     let (project, repo) = open_repository(req, user, project)?;
-    check_scope(req, service, &project)?;
+    let auth_user = check_scope(req, service, &project)?;
 
     match req.headers.get::<ContentType>() {
         Some(&ContentType(Mime(TopLevel::Application, SubLevel::Ext(ref s), _)))
@@ -152,34 +240,77 @@ fn handle_service_rpc(req: &mut Request, user: &str, project: &str, service: &st
         _ => return Err(IronError::new(AppError::from(""), status::Unauthorized)),
     }
 
-    let mut body_reader: Box<Read> = match req.headers.get::<ContentEncoding>() {
-        Some(&ContentEncoding(ref enc)) => {
-            if enc.iter()
-                .find(|&e| if let &Encoding::Gzip = e { true } else { false })
-                .is_some()
-            {
-                GzDecoder::new(&mut req.body)
-                    .map_err(|err| IronError::new(err, status::InternalServerError))
-                    .map(Box::new)?
-            } else {
-                Box::new(&mut req.body)
-            }
-        }
-        _ => Box::new(&mut req.body),
-    };
+    // In the protocol version 2, `ls-refs` and `fetch` commands are sent to this endpoint and are
+    // handled by `git upload-pack` itself.
+    let protocol = get_git_protocol(req);
+    let timeouts = req.extensions.get::<Config>().unwrap().rpc_timeouts();
 
-    let body = repo.run_rpc_command(service, Some(&mut body_reader))
-        .map_err(|err| IronError::new(err, status::InternalServerError))?;
+    let mut envs = vec![(hooks::ENV_PROJECT_ID, project.id.to_string())];
+    if let Some(ref auth_user) = auth_user {
+        envs.push((hooks::ENV_USER_ID, auth_user.id.to_string()));
+    }
 
-    Ok(Response::with((
+    let mut process = repo.run_rpc_command(
+        service,
+        false,
+        protocol.as_ref().map(|s| s.as_str()),
+        &envs,
+        timeouts,
+    ).map_err(|err| IronError::new(err, status::InternalServerError))?;
+
+    {
+        let mut body_reader: Box<Read> = match req.headers.get::<ContentEncoding>() {
+            Some(&ContentEncoding(ref enc)) => {
+                if enc.iter()
+                    .find(|&e| if let &Encoding::Gzip = e { true } else { false })
+                    .is_some()
+                {
+                    GzDecoder::new(&mut req.body)
+                        .map_err(|err| IronError::new(err, status::InternalServerError))
+                        .map(Box::new)?
+                } else {
+                    Box::new(&mut req.body)
+                }
+            }
+            _ => Box::new(&mut req.body),
+        };
+        process.write_input(&mut body_reader).map_err(|err| {
+            IronError::new(err, status::InternalServerError)
+        })?;
+    }
+
+    let mut response = Response::with((
         status::Ok,
         Header(ContentType(Mime(
             TopLevel::Application,
             SubLevel::Ext(format!("x-git-{}-result", service)),
             Vec::new(),
         ))),
-        body,
-    )))
+    ));
+    response.body = Some(Box::new(RpcBody {
+        header: Vec::new(),
+        process: process,
+    }));
+    Ok(response)
+}
+
+/// The body of responses which streams the output of git.
+///
+/// Since the body is sent with chunked encoding, a failure of git is reported to the client by
+/// closing the connection before the last chunk.
+struct RpcBody {
+    header: Vec<u8>,
+    process: RpcProcess,
+}
+
+impl WriteBody for RpcBody {
+    fn write_body(&mut self, res: &mut Write) -> io::Result<()> {
+        res.write_all(&self.header)?;
+        io::copy(&mut self.process, res).map(|_| ()).map_err(|err| {
+            let _ = writeln!(&mut io::stderr(), "failed to send the output of git: {}", err);
+            err
+        })
+    }
 }
 
 
@@ -189,18 +320,26 @@ fn handle_service_rpc(req: &mut Request, user: &str, project: &str, service: &st
 struct InfoRefs;
 
 fn info_refs(req: &mut Request, user: String, project: String) -> IronResult<Response> {
-    let service = get_service_name(req)?;
+    let service = match get_service_name(req)? {
+        Some(service) => service,
+        None => return dumb_info_refs(req, &user, &project),
+    };
     let (project, repo) = open_repository(req, &user, &project)?;
     check_scope(req, service, &project)?;
 
-    let mut body = packet_write(&format!("# service=git-{}\n", service));
-    body.extend(b"0000");
-    let refs = repo.run_rpc_command(service, None).map_err(|err| {
-        IronError::new(err, status::InternalServerError)
-    })?;
-    body.extend(refs);
+    // The capability advertisement of the protocol version 2 is sent without the service header.
+    let protocol = get_git_protocol(req);
+    let protocol = protocol.as_ref().map(|s| s.as_str());
+    let mut header = Vec::new();
+    if !is_protocol_v2(service, protocol) {
+        header.extend(packet_write(&format!("# service=git-{}\n", service)));
+        header.extend(b"0000");
+    }
+    let timeouts = req.extensions.get::<Config>().unwrap().rpc_timeouts();
+    let process = repo.run_rpc_command(service, true, protocol, &[], timeouts)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?;
 
-    Ok(Response::with((
+    let mut response = Response::with((
         status::Ok,
         Header(CacheControl(vec![CacheDirective::NoCache])),
         Header(ContentType(Mime(
@@ -208,8 +347,12 @@ fn info_refs(req: &mut Request, user: String, project: String) -> IronResult<Res
             SubLevel::Ext(format!("x-git-{}-advertisement", service)),
             Vec::new(),
         ))),
-        body,
-    )))
+    ));
+    response.body = Some(Box::new(RpcBody {
+        header: header,
+        process: process,
+    }));
+    Ok(response)
 }
 
 
@@ -233,3 +376,136 @@ struct UploadPack;
 fn upload_pack(req: &mut Request, user: String, project: String) -> IronResult<Response> {
     handle_service_rpc(req, &user, &project, "upload-pack")
 }
+
+
+
+// The dumb protocol, which serves the files in the repository for clients which do not support the
+// smart protocol. It is read-only, and `info/refs` and `objects/info/packs` are generated on the fly
+// instead of relying on `git update-server-info`.
+
+/// Opens the repository and checks whether the request is allowed to fetch it.
+fn open_readable_repository(req: &mut Request, user: &str, project: &str) -> IronResult<(Project, Repository)> {
+    let (project, repo) = open_repository(req, user, project)?;
+    check_scope(req, "upload-pack", &project)?;
+    Ok((project, repo))
+}
+
+/// Returns a response of the generated file, which must not be cached.
+fn dumb_text_response(body: Vec<u8>) -> Response {
+    Response::with((
+        status::Ok,
+        Header(CacheControl(vec![CacheDirective::NoCache])),
+        Header(ContentType(
+            Mime(TopLevel::Text, SubLevel::Plain, Vec::new()),
+        )),
+        body,
+    ))
+}
+
+/// Returns a response of the file in the object database, which never changes once written.
+///
+/// Only the objects of public projects may be kept by shared caches, since the others must not be
+/// served to anyone without the permission.
+fn dumb_object_response(project: &Project, repo: &Repository, path: &str, content_type: &str) -> IronResult<Response> {
+    let file = File::open(repo.object_file_path(path)).map_err(|_| {
+        IronError::new(AppError::from(""), status::NotFound)
+    })?;
+    let cache_control = if project.visibility() == Visibility::Public {
+        vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(365 * 24 * 60 * 60),
+        ]
+    } else {
+        vec![CacheDirective::Private, CacheDirective::NoStore]
+    };
+    Ok(Response::with((
+        status::Ok,
+        Header(CacheControl(cache_control)),
+        Header(ContentType(Mime(
+            TopLevel::Application,
+            SubLevel::Ext(content_type.to_owned()),
+            Vec::new(),
+        ))),
+        file,
+    )))
+}
+
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len &&
+        s.bytes().all(|b| match b {
+            b'0'...b'9' | b'a'...b'f' => true,
+            _ => false,
+        })
+}
+
+fn dumb_info_refs(req: &mut Request, user: &str, project: &str) -> IronResult<Response> {
+    let (_, repo) = open_readable_repository(req, user, project)?;
+    let body = repo.dumb_info_refs().map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    Ok(dumb_text_response(body))
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/:user/:project/HEAD", handler = "dumb_head")]
+struct DumbHead;
+
+fn dumb_head(req: &mut Request, user: String, project: String) -> IronResult<Response> {
+    let (_, repo) = open_readable_repository(req, &user, &project)?;
+    let body = repo.dumb_head().map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    Ok(dumb_text_response(body))
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/:user/:project/objects/info/packs", handler = "dumb_info_packs")]
+struct DumbInfoPacks;
+
+fn dumb_info_packs(req: &mut Request, user: String, project: String) -> IronResult<Response> {
+    let (_, repo) = open_readable_repository(req, &user, &project)?;
+    let body = repo.dumb_info_packs().map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    Ok(dumb_text_response(body))
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/:user/:project/objects/:dir/:file", handler = "dumb_loose_object")]
+struct DumbLooseObject;
+
+fn dumb_loose_object(req: &mut Request, user: String, project: String, dir: String, file: String) -> IronResult<Response> {
+    let (project, repo) = open_readable_repository(req, &user, &project)?;
+    if !is_hex(&dir, 2) || !is_hex(&file, 38) {
+        return Err(IronError::new(AppError::from(""), status::NotFound));
+    }
+    dumb_object_response(&project, &repo, &format!("{}/{}", dir, file), "x-git-loose-object")
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/:user/:project/objects/pack/:file", handler = "dumb_pack_file")]
+struct DumbPackFile;
+
+fn dumb_pack_file(req: &mut Request, user: String, project: String, file: String) -> IronResult<Response> {
+    let (project, repo) = open_readable_repository(req, &user, &project)?;
+    let content_type = if file.ends_with(".pack") {
+        "x-git-packed-objects"
+    } else if file.ends_with(".idx") {
+        "x-git-packed-objects-toc"
+    } else {
+        return Err(IronError::new(AppError::from(""), status::NotFound));
+    };
+    let name = file.trim_left_matches("pack-").trim_right_matches(".pack").trim_right_matches(".idx");
+    if !file.starts_with("pack-") || !is_hex(name, 40) {
+        return Err(IronError::new(AppError::from(""), status::NotFound));
+    }
+    dumb_object_response(&project, &repo, &format!("pack/{}", file), content_type)
+}
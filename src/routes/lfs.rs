@@ -0,0 +1,473 @@
+//! Git LFS API, served next to the smart HTTP protocol.
+//!
+//! The batch API with the `basic` transfer adapter, and the locking API are supported. The permissions
+//! are checked in the same way as fetches (for downloads and listing locks) and pushes (for uploads and
+//! managing locks).
+
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::Read;
+use iron::prelude::*;
+use iron::status;
+use iron::headers::ContentType;
+use iron::mime::{Mime, TopLevel, SubLevel};
+use iron::modifiers::Header;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value as JsonValue};
+use url::Url;
+
+use config::Config;
+use db::DB;
+use error::{AppError, AppErrorKind};
+use lfs;
+use models::{LfsLock, LfsObject, NewLfsLock, NewLfsObject, Project, User};
+use models::projects::AccessLevel;
+use super::git::{open_repository, check_scope};
+
+
+/// The maximum size of JSON requests, in bytes.
+const MAX_REQUEST_SIZE: u64 = 1024 * 1024;
+
+/// The default and maximum number of locks returned at once.
+const MAX_LOCKS: usize = 100;
+
+
+fn lfs_content_type() -> ContentType {
+    ContentType(Mime(
+        TopLevel::Application,
+        SubLevel::Ext("vnd.git-lfs+json".to_owned()),
+        Vec::new(),
+    ))
+}
+
+/// Returns an error in the form defined by the LFS API.
+fn lfs_error(status: status::Status, message: &str) -> IronError {
+    let body = json!({ "message": message }).to_string();
+    IronError::new(
+        AppError::from(message),
+        (status, Header(lfs_content_type()), body),
+    )
+}
+
+/// Reads the request body as JSON.
+fn read_json<T: DeserializeOwned>(req: &mut Request) -> IronResult<T> {
+    let mut body = Vec::new();
+    req.body
+        .by_ref()
+        .take(MAX_REQUEST_SIZE)
+        .read_to_end(&mut body)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?;
+    serde_json::from_slice(&body).map_err(|err| {
+        lfs_error(status::BadRequest, &format!("Invalid request: {}", err))
+    })
+}
+
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    operation: String,
+    #[serde(default)]
+    transfers: Vec<String>,
+    objects: Vec<BatchObject>,
+}
+
+#[derive(Deserialize)]
+struct BatchObject {
+    oid: String,
+    size: i64,
+}
+
+
+#[derive(Route)]
+#[post(path = "/:user/:project/info/lfs/objects/batch", handler = "batch")]
+pub(super) struct Batch;
+
+fn batch(req: &mut Request, user: String, project: String) -> IronResult<Response> {
+    let (project, _) = open_repository(req, &user, &project)?;
+
+    let params: BatchRequest = read_json(req)?;
+
+    let is_upload = match params.operation.as_str() {
+        "download" => false,
+        "upload" => true,
+        operation => {
+            return Err(lfs_error(
+                status::UnprocessableEntity,
+                &format!("Unsupported operation: {}", operation),
+            ))
+        }
+    };
+    if !params.transfers.is_empty() && !params.transfers.iter().any(|t| t == "basic") {
+        return Err(lfs_error(
+            status::UnprocessableEntity,
+            "Only the basic transfer adapter is supported",
+        ));
+    }
+    check_scope(req, if is_upload { "receive-pack" } else { "upload-pack" }, &project)?;
+
+    let conn = DB::from_req(req).unwrap();
+    let root = req.extensions.get::<Config>().unwrap().repository_root.clone();
+
+    // The actions are sent to the endpoints next to this one, with the same credentials.
+    let mut objects_url: Url = req.url.clone().into();
+    objects_url.set_query(None);
+    objects_url.path_segments_mut().unwrap().pop();
+    let header = match req.headers.get_raw("Authorization").and_then(|raw| raw.first()) {
+        Some(value) => json!({ "Authorization": String::from_utf8_lossy(value) }),
+        None => json!({}),
+    };
+
+    let mut objects = Vec::new();
+    for object in params.objects {
+        if !lfs::is_valid_oid(&object.oid) || object.size < 0 {
+            objects.push(json!({
+                "oid": object.oid,
+                "size": object.size,
+                "error": { "code": 422, "message": "Invalid object" },
+            }));
+            continue;
+        }
+
+        let is_stored = LfsObject::find(&conn, project.id, &object.oid)
+            .map_err(|err| IronError::new(err, status::InternalServerError))?
+            .is_some() && lfs::object_path(&root, project.id, &object.oid).is_file();
+
+        let mut href = objects_url.clone();
+        href.path_segments_mut().unwrap().push(&object.oid);
+        let item = match (is_upload, is_stored) {
+            (false, false) => {
+                json!({
+                    "oid": object.oid,
+                    "size": object.size,
+                    "error": { "code": 404, "message": "Object does not exist" },
+                })
+            }
+            (false, true) => {
+                json!({
+                    "oid": object.oid,
+                    "size": object.size,
+                    "authenticated": true,
+                    "actions": {
+                        "download": { "href": href.as_str(), "header": header.clone() },
+                    },
+                })
+            }
+            // Objects which have already been uploaded are omitted from the upload.
+            (true, true) => json!({ "oid": object.oid, "size": object.size }),
+            (true, false) => {
+                href.path_segments_mut().unwrap().push(&object.size.to_string());
+                json!({
+                    "oid": object.oid,
+                    "size": object.size,
+                    "authenticated": true,
+                    "actions": {
+                        "upload": { "href": href.as_str(), "header": header.clone() },
+                    },
+                })
+            }
+        };
+        objects.push(item);
+    }
+
+    let body = json!({
+        "transfer": "basic",
+        "objects": objects,
+    }).to_string();
+    Ok(Response::with((status::Ok, Header(lfs_content_type()), body)))
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/:user/:project/info/lfs/objects/:oid", handler = "download")]
+pub(super) struct Download;
+
+fn download(req: &mut Request, user: String, project: String, oid: String) -> IronResult<Response> {
+    let (project, _) = open_repository(req, &user, &project)?;
+    check_scope(req, "upload-pack", &project)?;
+    if !lfs::is_valid_oid(&oid) {
+        return Err(lfs_error(status::NotFound, "Object does not exist"));
+    }
+
+    let conn = DB::from_req(req).unwrap();
+    let root = req.extensions.get::<Config>().unwrap().repository_root.clone();
+    LfsObject::find(&conn, project.id, &oid)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?
+        .ok_or_else(|| lfs_error(status::NotFound, "Object does not exist"))?;
+    let file = File::open(lfs::object_path(&root, project.id, &oid)).map_err(|_| {
+        lfs_error(status::NotFound, "Object does not exist")
+    })?;
+
+    Ok(Response::with((
+        status::Ok,
+        Header(ContentType(
+            Mime(TopLevel::Application, SubLevel::Ext("octet-stream".to_owned()), Vec::new()),
+        )),
+        file,
+    )))
+}
+
+
+
+#[derive(Route)]
+#[put(path = "/:user/:project/info/lfs/objects/:oid/:size", handler = "upload")]
+pub(super) struct Upload;
+
+fn upload(req: &mut Request, user: String, project: String, oid: String, size: i64) -> IronResult<Response> {
+    let (project, _) = open_repository(req, &user, &project)?;
+    check_scope(req, "receive-pack", &project)?;
+    if !lfs::is_valid_oid(&oid) || size < 0 {
+        return Err(lfs_error(status::UnprocessableEntity, "Invalid object"));
+    }
+
+    let conn = DB::from_req(req).unwrap();
+    let root = req.extensions.get::<Config>().unwrap().repository_root.clone();
+    lfs::store(&root, project.id, &oid, size, &mut req.body).map_err(|err| {
+        match *err.kind() {
+            AppErrorKind::Invalid(ref message) => lfs_error(status::UnprocessableEntity, message),
+            _ => IronError::new(err, status::InternalServerError),
+        }
+    })?;
+
+    // The object may have been recorded by a concurrent upload of the same content.
+    let stored = LfsObject::find(&conn, project.id, &oid).map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    if stored.is_none() {
+        let new_object = NewLfsObject {
+            project_id: project.id,
+            oid: oid.clone(),
+            size: size,
+        };
+        if let Err(err) = new_object.insert(&conn) {
+            let stored = LfsObject::find(&conn, project.id, &oid).map_err(|err| {
+                IronError::new(err, status::InternalServerError)
+            })?;
+            if stored.is_none() {
+                return Err(IronError::new(err, status::InternalServerError));
+            }
+        }
+    }
+
+    Ok(Response::with(status::Ok))
+}
+
+
+
+fn lock_to_json(owner: &User, lock: &LfsLock) -> JsonValue {
+    json!({
+        "id": lock.id.to_string(),
+        "path": lock.path,
+        "locked_at": lock.created_at.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        "owner": { "name": owner.name },
+    })
+}
+
+/// Returns the page of locks starting from the one whose ID is `cursor`, and the cursor of the next page.
+fn paginate<'a>(
+    locks: &'a [(User, LfsLock)],
+    cursor: Option<i32>,
+    limit: usize,
+) -> (&'a [(User, LfsLock)], Option<String>) {
+    let start = cursor
+        .map(|cursor| locks.iter().take_while(|&&(_, ref lock)| lock.id < cursor).count())
+        .unwrap_or(0);
+    let end = ::std::cmp::min(start + limit, locks.len());
+    let next_cursor = locks.get(end).map(|&(_, ref lock)| lock.id.to_string());
+    (&locks[start..end], next_cursor)
+}
+
+/// Returns the user who is pushing to the project, which is required to manage locks.
+fn require_writer(req: &mut Request, project: &Project) -> IronResult<User> {
+    // Anonymous users never have the write access, so `check_scope` always authenticates the user.
+    check_scope(req, "receive-pack", project)?.ok_or_else(|| {
+        lfs_error(status::Unauthorized, "Authentication is required")
+    })
+}
+
+
+
+#[derive(Route)]
+#[get(path = "/:user/:project/info/lfs/locks", handler = "list_locks")]
+pub(super) struct ListLocks;
+
+fn list_locks(req: &mut Request, user: String, project: String) -> IronResult<Response> {
+    let (project, _) = open_repository(req, &user, &project)?;
+    check_scope(req, "upload-pack", &project)?;
+
+    let url: Url = req.url.clone().into();
+    let mut path = None;
+    let mut id: Option<i32> = None;
+    let mut cursor = None;
+    let mut limit = None;
+    for (key, val) in url.query_pairs() {
+        match key.borrow() {
+            "path" => path = Some(val.into_owned()),
+            "id" => id = val.parse().ok(),
+            "cursor" => cursor = val.parse().ok(),
+            "limit" => limit = val.parse().ok(),
+            _ => (),
+        }
+    }
+    let limit = ::std::cmp::min(limit.unwrap_or(MAX_LOCKS), MAX_LOCKS);
+
+    let conn = DB::from_req(req).unwrap();
+    let locks: Vec<_> = LfsLock::load_by_project(&conn, project.id)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?
+        .into_iter()
+        .filter(|&(_, ref lock)| path.as_ref().map_or(true, |path| lock.path == *path))
+        .filter(|&(_, ref lock)| id.map_or(true, |id| lock.id == id))
+        .collect();
+    let (locks, next_cursor) = paginate(&locks, cursor, limit);
+
+    let body = json!({
+        "locks": locks.iter().map(|&(ref owner, ref lock)| lock_to_json(owner, lock)).collect::<Vec<_>>(),
+        "next_cursor": next_cursor,
+    }).to_string();
+    Ok(Response::with((status::Ok, Header(lfs_content_type()), body)))
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/:user/:project/info/lfs/locks", handler = "create_lock")]
+pub(super) struct CreateLock;
+
+fn create_lock(req: &mut Request, user: String, project: String) -> IronResult<Response> {
+    #[derive(Deserialize)]
+    struct Params {
+        path: String,
+    }
+
+    let (project, _) = open_repository(req, &user, &project)?;
+    let params: Params = read_json(req)?;
+    let user = require_writer(req, &project)?;
+    if params.path.is_empty() {
+        return Err(lfs_error(status::BadRequest, "The path is empty"));
+    }
+
+    let conn = DB::from_req(req).unwrap();
+    let new_lock = NewLfsLock {
+        project_id: project.id,
+        user_id: user.id,
+        path: params.path.clone(),
+    };
+    // The unique constraint rejects the lock if the file is locked concurrently.
+    if let Err(err) = new_lock.insert(&conn) {
+        let existing = LfsLock::find_by_path(&conn, project.id, &params.path)
+            .map_err(|err| IronError::new(err, status::InternalServerError))?;
+        return match existing {
+            Some((owner, lock)) => {
+                let body = json!({
+                    "lock": lock_to_json(&owner, &lock),
+                    "message": format!("The file is already locked by {}", owner.name),
+                }).to_string();
+                Err(IronError::new(
+                    AppError::from("The file is already locked"),
+                    (status::Conflict, Header(lfs_content_type()), body),
+                ))
+            }
+            None => Err(IronError::new(err, status::InternalServerError)),
+        };
+    }
+    let (owner, lock) = LfsLock::find_by_path(&conn, project.id, &params.path)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?
+        .ok_or_else(|| {
+            IronError::new(AppError::from("failed to find the created lock"), status::InternalServerError)
+        })?;
+
+    let body = json!({ "lock": lock_to_json(&owner, &lock) }).to_string();
+    Ok(Response::with((status::Created, Header(lfs_content_type()), body)))
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/:user/:project/info/lfs/locks/verify", handler = "verify_locks")]
+pub(super) struct VerifyLocks;
+
+fn verify_locks(req: &mut Request, user: String, project: String) -> IronResult<Response> {
+    #[derive(Deserialize)]
+    struct Params {
+        cursor: Option<String>,
+        limit: Option<usize>,
+    }
+
+    let (project, _) = open_repository(req, &user, &project)?;
+    let params: Params = read_json(req)?;
+    let user = require_writer(req, &project)?;
+    let cursor = params.cursor.and_then(|cursor| cursor.parse().ok());
+    let limit = ::std::cmp::min(params.limit.unwrap_or(MAX_LOCKS), MAX_LOCKS);
+
+    let conn = DB::from_req(req).unwrap();
+    let locks = LfsLock::load_by_project(&conn, project.id).map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+    let (locks, next_cursor) = paginate(&locks, cursor, limit);
+
+    let mut ours = Vec::new();
+    let mut theirs = Vec::new();
+    for &(ref owner, ref lock) in locks {
+        if lock.user_id == user.id {
+            ours.push(lock_to_json(owner, lock));
+        } else {
+            theirs.push(lock_to_json(owner, lock));
+        }
+    }
+
+    let body = json!({
+        "ours": ours,
+        "theirs": theirs,
+        "next_cursor": next_cursor,
+    }).to_string();
+    Ok(Response::with((status::Ok, Header(lfs_content_type()), body)))
+}
+
+
+
+#[derive(Route)]
+#[post(path = "/:user/:project/info/lfs/locks/:id/unlock", handler = "unlock")]
+pub(super) struct Unlock;
+
+fn unlock(req: &mut Request, user: String, project: String, id: i32) -> IronResult<Response> {
+    #[derive(Deserialize)]
+    struct Params {
+        #[serde(default)]
+        force: bool,
+    }
+
+    let (project, _) = open_repository(req, &user, &project)?;
+    let params: Params = read_json(req)?;
+    let user = require_writer(req, &project)?;
+
+    let conn = DB::from_req(req).unwrap();
+    let (owner, lock) = LfsLock::find(&conn, project.id, id)
+        .map_err(|err| IronError::new(err, status::InternalServerError))?
+        .ok_or_else(|| lfs_error(status::NotFound, "The lock does not exist"))?;
+
+    // Only maintainers are allowed to break the locks of other users.
+    if lock.user_id != user.id {
+        if !params.force {
+            return Err(lfs_error(
+                status::Forbidden,
+                &format!("The file is locked by {}, and requires force to unlock", owner.name),
+            ));
+        }
+        let access_level = project.access_level(&conn, Some(user.id)).map_err(|err| {
+            IronError::new(err, status::InternalServerError)
+        })?;
+        if access_level < AccessLevel::Admin {
+            return Err(lfs_error(
+                status::Forbidden,
+                "Only maintainers are allowed to unlock the files locked by other users",
+            ));
+        }
+    }
+
+    lock.remove(&conn).map_err(|err| {
+        IronError::new(err, status::InternalServerError)
+    })?;
+
+    let body = json!({ "lock": lock_to_json(&owner, &lock) }).to_string();
+    Ok(Response::with((status::Ok, Header(lfs_content_type()), body)))
+}
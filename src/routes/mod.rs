@@ -1,4 +1,5 @@
 mod git;
+mod lfs;
 mod api {
     #[path = "v1/mod.rs"]
     pub mod v1;
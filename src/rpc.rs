@@ -0,0 +1,434 @@
+//! Processes of `git <service> --stateless-rpc`, spawned by `Repository::run_rpc_command`.
+//!
+//! The request body is written into the standard input of git while its standard output is read by
+//! another thread, so that git never blocks on a full pipe. The output is passed on to the client
+//! through a bounded queue once the input has been written, and is buffered up to
+//! `MAX_BUFFERED_OUTPUT` until then. A watchdog thread kills processes which exceed the timeouts.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, ChildStderr, ChildStdout};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use error::{AppResult, AppError};
+
+
+/// The size of chunks read from the standard output, in bytes.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of chunks which are read from git ahead of the client.
+const MAX_PENDING_CHUNKS: usize = 16;
+
+/// The maximum size of the output buffered while the input is being written, in bytes.
+///
+/// git writes little before it has read the whole request, and is killed if it writes more.
+const MAX_BUFFERED_OUTPUT: usize = 64 * 1024 * 1024;
+
+/// The maximum length of the standard error kept for logging, in bytes.
+const MAX_STDERR: usize = 64 * 1024;
+
+/// The interval of checking the timeouts, in milliseconds.
+const WATCHDOG_INTERVAL: u64 = 500;
+
+/// The interval of checking the exit of git, in milliseconds.
+const WAIT_INTERVAL: u64 = 10;
+
+
+/// The limits of the duration of a process.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// The maximum duration of the whole process.
+    pub total: Duration,
+    /// The maximum duration without any input or output.
+    pub idle: Duration,
+}
+
+
+/// The state shared with the watchdog and the thread reading the standard output.
+struct State {
+    /// Locked only for a moment, so that the watchdog can always kill the process.
+    child: Mutex<Child>,
+    last_activity: Mutex<Instant>,
+    input_closed: AtomicBool,
+    /// Whether the process has exited and been reaped.
+    finished: AtomicBool,
+    timed_out: AtomicBool,
+}
+
+impl State {
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    fn kill(&self) {
+        let _ = self.child.lock().unwrap().kill();
+    }
+}
+
+
+/// A running git process.
+///
+/// The output is read through `Read`, which fails if git exits with non-zero status or times out.
+/// The process is killed if it is dropped before the output is read to the end.
+pub struct RpcProcess {
+    service: String,
+    state: Arc<State>,
+    stdin: Option<ChildStdin>,
+    stdout: Receiver<io::Result<Vec<u8>>>,
+    stderr: Option<thread::JoinHandle<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl RpcProcess {
+    /// Starts pumping the output of the child, whose standard output and error must be piped.
+    pub fn new(service: &str, mut child: Child, timeouts: Timeouts) -> Self {
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().expect("the standard output is not piped");
+        let stderr = child.stderr.take().expect("the standard error is not piped");
+
+        let state = Arc::new(State {
+            input_closed: AtomicBool::new(stdin.is_none()),
+            child: Mutex::new(child),
+            last_activity: Mutex::new(Instant::now()),
+            finished: AtomicBool::new(false),
+            timed_out: AtomicBool::new(false),
+        });
+
+        let (tx, rx) = mpsc::sync_channel(MAX_PENDING_CHUNKS);
+        {
+            let state = state.clone();
+            thread::spawn(move || pump_stdout(stdout, tx, &state));
+        }
+        let stderr = thread::spawn(move || read_stderr(stderr));
+        {
+            let state = state.clone();
+            thread::spawn(move || watchdog(&state, timeouts));
+        }
+
+        RpcProcess {
+            service: service.to_owned(),
+            state: state,
+            stdin: stdin,
+            stdout: rx,
+            stderr: Some(stderr),
+            chunk: Vec::new(),
+            pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Writes the input into the standard input of git, and closes it.
+    pub fn write_input(&mut self, input: &mut Read) -> AppResult<()> {
+        let mut stdin = match self.stdin.take() {
+            Some(stdin) => stdin,
+            None => return Ok(()),
+        };
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let len = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.state.kill();
+                    let _ = self.finish();
+                    return Err(err.into());
+                }
+            };
+            if let Err(err) = stdin.write_all(&buf[..len]) {
+                // git has exited before reading the whole input, and the reason is in the standard error.
+                self.state.kill();
+                return self.finish().and(Err(err.into()));
+            }
+            self.state.touch();
+        }
+        drop(stdin);
+        self.state.input_closed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Waits for the exit of git, and logs its standard error.
+    fn finish(&mut self) -> AppResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.stdin.take();
+        self.state.input_closed.store(true, Ordering::SeqCst);
+        let status = loop {
+            if let Some(status) = self.state.child.lock().unwrap().try_wait()? {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(WAIT_INTERVAL));
+        };
+        self.state.finished.store(true, Ordering::SeqCst);
+        let stderr = self.stderr
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        let stderr = String::from_utf8_lossy(&stderr);
+        if !stderr.trim().is_empty() {
+            let _ = writeln!(&mut io::stderr(), "git {}: {}", self.service, stderr.trim_right());
+        }
+
+        if self.state.timed_out.load(Ordering::SeqCst) {
+            Err(AppError::from(format!("`git {}` was killed by timeout", self.service)))
+        } else if !status.success() {
+            Err(AppError::from(format!(
+                "`git {}` was exited with non-zero status: {}",
+                self.service,
+                stderr
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Read for RpcProcess {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.stdout.recv() {
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                    self.state.touch();
+                }
+                Ok(Err(err)) => return Err(err),
+                Err(_) => {
+                    self.finish().map_err(|err| {
+                        io::Error::new(io::ErrorKind::Other, err.to_string())
+                    })?;
+                    return Ok(0);
+                }
+            }
+        }
+        let len = ::std::cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Drop for RpcProcess {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.state.kill();
+            let _ = self.finish();
+        }
+    }
+}
+
+
+fn pump_stdout(mut stdout: ChildStdout, tx: SyncSender<io::Result<Vec<u8>>>, state: &State) {
+    // Chunks not yet passed to the client. While the input is being written, nobody reads the
+    // queue and git would block on its output, so the chunks are kept here instead.
+    let mut buffered = VecDeque::new();
+    let mut buffered_len = 0;
+    loop {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let eof = match stdout.read(&mut buf) {
+            Ok(0) => true,
+            Ok(len) => {
+                buf.truncate(len);
+                state.touch();
+                buffered_len += len;
+                buffered.push_back(Ok(buf));
+                false
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                buffered.push_back(Err(err));
+                true
+            }
+        };
+        if eof {
+            break;
+        }
+
+        while let Some(chunk) = buffered.pop_front() {
+            let len = chunk.as_ref().map(|c| c.len()).unwrap_or(0);
+            let sent = if state.input_closed.load(Ordering::SeqCst) {
+                tx.send(chunk).map_err(|_| None)
+            } else {
+                tx.try_send(chunk).map_err(|err| match err {
+                    TrySendError::Full(chunk) => Some(chunk),
+                    TrySendError::Disconnected(_) => None,
+                })
+            };
+            match sent {
+                Ok(()) => buffered_len -= len,
+                Err(Some(chunk)) => {
+                    buffered.push_front(chunk);
+                    break;
+                }
+                // The receiver has gone if the client disconnected.
+                Err(None) => return,
+            }
+        }
+        if buffered_len > MAX_BUFFERED_OUTPUT {
+            state.kill();
+            let _ = tx.send(Err(io::Error::new(
+                io::ErrorKind::Other,
+                "too much output before the end of the input",
+            )));
+            return;
+        }
+    }
+
+    for chunk in buffered {
+        if tx.send(chunk).is_err() {
+            break;
+        }
+    }
+}
+
+fn read_stderr(mut stderr: ChildStderr) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let _ = stderr.by_ref().take(MAX_STDERR as u64).read_to_end(&mut buf);
+    // The rest is discarded, but still has to be read so that git does not block.
+    let _ = io::copy(&mut stderr, &mut io::sink());
+    buf
+}
+
+fn watchdog(state: &State, timeouts: Timeouts) {
+    let started = Instant::now();
+    while !state.finished.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(WATCHDOG_INTERVAL));
+        let idle = state.last_activity.lock().unwrap().elapsed();
+        if started.elapsed() > timeouts.total || idle > timeouts.idle {
+            state.timed_out.store(true, Ordering::SeqCst);
+            state.kill();
+            break;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Cursor, Read, Write};
+    use std::path::Path;
+    use std::process::{self, Command, Stdio};
+    use std::time::{Duration, Instant};
+    use super::{RpcProcess, Timeouts};
+
+    const TIMEOUTS: Timeouts = Timeouts {
+        total: Duration::from_secs(60),
+        idle: Duration::from_secs(30),
+    };
+
+    fn spawn(service: &str, command: &mut Command, timeouts: Timeouts) -> RpcProcess {
+        let child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        RpcProcess::new(service, child, timeouts)
+    }
+
+    /// Returns pseudo-random bytes, which git cannot compress.
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut x: u32 = 0x1234_5678;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> Vec<u8> {
+        let output = Command::new("git")
+            .args(&["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+        output.stdout
+    }
+
+    #[test]
+    fn output_written_while_reading_input() {
+        // `cat` writes as much as it reads, far more than the pipe and the queue can hold.
+        let input = random_bytes(16 * 1024 * 1024);
+        let mut process = spawn("cat", &mut Command::new("cat"), TIMEOUTS);
+        process.write_input(&mut Cursor::new(&input[..])).unwrap();
+        let mut output = Vec::new();
+        process.read_to_end(&mut output).unwrap();
+        assert!(output == input);
+    }
+
+    #[test]
+    fn hung_process_killed_by_timeout() {
+        // The output is closed, but the process does not exit.
+        let timeouts = Timeouts {
+            total: Duration::from_secs(60),
+            idle: Duration::from_secs(1),
+        };
+        let mut command = Command::new("sh");
+        command.args(&["-c", "exec >&-; exec sleep 60"]);
+        let mut process = spawn("sh", &mut command, timeouts);
+        let started = Instant::now();
+        assert!(process.read_to_end(&mut Vec::new()).is_err());
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn receive_large_pack() {
+        let root = env::temp_dir().join(format!("gallium-rpc-test-{}", process::id()));
+        let (work, remote) = (root.join("work"), root.join("remote.git"));
+        fs::create_dir_all(&work).unwrap();
+        git(&root, &["init", "-q", "--bare", remote.to_str().unwrap()]);
+        git(&work, &["init", "-q"]);
+        fs::File::create(work.join("blob"))
+            .unwrap()
+            .write_all(&random_bytes(32 * 1024 * 1024))
+            .unwrap();
+        git(&work, &["add", "blob"]);
+        git(&work, &["commit", "-q", "-m", "Add a large blob"]);
+        let head = String::from_utf8(git(&work, &["rev-parse", "HEAD"])).unwrap();
+
+        let mut pack_objects = Command::new("git")
+            .args(&["pack-objects", "--stdout", "--revs", "-q"])
+            .current_dir(&work)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        pack_objects.stdin.take().unwrap().write_all(head.as_bytes()).unwrap();
+        let mut pack = Vec::new();
+        pack_objects.stdout.take().unwrap().read_to_end(&mut pack).unwrap();
+        assert!(pack_objects.wait().unwrap().success());
+
+        let command = format!("{} {} refs/heads/master\0report-status\n", "0".repeat(40), head.trim());
+        let mut input = format!("{:04x}{}0000", command.len() + 4, command).into_bytes();
+        input.extend(pack);
+
+        let mut receive_pack = Command::new("git");
+        receive_pack.args(&["receive-pack", "--stateless-rpc", remote.to_str().unwrap()]);
+        let mut process = spawn("receive-pack", &mut receive_pack, TIMEOUTS);
+        process.write_input(&mut Cursor::new(input)).unwrap();
+        let mut output = Vec::new();
+        process.read_to_end(&mut output).unwrap();
+        let output = String::from_utf8_lossy(&output).into_owned();
+        let pushed = String::from_utf8(git(&root, &["--git-dir", remote.to_str().unwrap(), "rev-parse", "master"]));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(output.contains("unpack ok"), "{}", output);
+        assert!(output.contains("ok refs/heads/master"), "{}", output);
+        assert_eq!(pushed.unwrap(), head);
+    }
+}
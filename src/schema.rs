@@ -1,5 +1,68 @@
 // This file is automatically generated by diesel_cli.
 
+table! {
+    lfs_locks (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        project_id -> Int4,
+        user_id -> Int4,
+        path -> Text,
+    }
+}
+
+table! {
+    lfs_objects (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        project_id -> Int4,
+        oid -> Text,
+        size -> Int8,
+    }
+}
+
+table! {
+    organization_members (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        organization_id -> Int4,
+        user_id -> Int4,
+        role -> Text,
+    }
+}
+
+table! {
+    organizations (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        name -> Text,
+        display_name -> Nullable<Text>,
+        description -> Nullable<Text>,
+    }
+}
+
+table! {
+    personal_access_tokens (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        expires_at -> Nullable<Timestamp>,
+        revoked -> Bool,
+    }
+}
+
+table! {
+    project_members (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        project_id -> Int4,
+        user_id -> Int4,
+        role -> Text,
+    }
+}
+
 table! {
     projects (id) {
         id -> Int4,
@@ -7,6 +70,20 @@ table! {
         user_id -> Int4,
         name -> Text,
         description -> Nullable<Text>,
+        visibility -> Text,
+        organization_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    protected_branches (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        project_id -> Int4,
+        pattern -> Text,
+        push_role -> Text,
+        allow_force_push -> Bool,
+        allow_deletion -> Bool,
     }
 }
 
@@ -17,6 +94,8 @@ table! {
         key -> Text,
         user_id -> Int4,
         description -> Nullable<Text>,
+        fingerprint -> Nullable<Text>,
+        fingerprint_md5 -> Nullable<Text>,
     }
 }
 
@@ -29,3 +108,33 @@ table! {
         bcrypt_hash -> Varchar,
     }
 }
+
+table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        webhook_id -> Nullable<Int4>,
+        event -> Text,
+        url -> Text,
+        payload -> Text,
+        signature -> Nullable<Text>,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+        status_code -> Nullable<Int4>,
+        response_body -> Nullable<Text>,
+        error -> Nullable<Text>,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        project_id -> Int4,
+        url -> Text,
+        secret -> Nullable<Text>,
+        events -> Array<Text>,
+        active -> Bool,
+    }
+}
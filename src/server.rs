@@ -1,17 +1,26 @@
 use iron::{Iron, Listening};
 
+use auth::AuthMiddleware;
 use db::{DB, DBMiddleware};
 use config::{Config, ConfigMiddleware};
 use error::AppResult;
+use models::{Project, SshKey};
 use routes::create_router;
+use webhooks;
 
 pub fn start(config: Config) -> AppResult<Listening> {
-    let db = DBMiddleware::new(DB::new(&config.database_url)?);
+    let db = DB::new(&config.database_url)?;
+    SshKey::fill_fingerprints(&*db.get_db_conn()?)?;
+    Project::install_all_hooks(&*db.get_db_conn()?, &config.hook_path()?)?;
+    webhooks::spawn_worker(db.clone());
+
+    let db = DBMiddleware::new(db);
     let config = ConfigMiddleware::new(config);
 
     let mut router = create_router();
     router.link_before(db);
     router.link_before(config);
+    router.link_before(AuthMiddleware);
 
     Iron::new(router).http("0.0.0.0:3000").map_err(Into::into)
 }
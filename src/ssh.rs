@@ -0,0 +1,90 @@
+//! Git over SSH, shared by `pubkey access` (invoked by OpenSSH) and the built-in SSH server.
+
+use std::path::Path;
+use std::process::Command;
+use diesel::pg::PgConnection;
+use shlex;
+
+use hooks;
+use models::Project;
+use models::projects::AccessLevel;
+
+
+/// Parses the command requested by the client, and returns the tuple of `(action, namespace, project)`.
+pub fn parse_ssh_command(s: &str) -> Result<(String, String, String), String> {
+    let command = shlex::split(s).ok_or_else(|| {
+        "failed to parse SSH_ORIGINAL_COMMAND".to_string()
+    })?;
+
+    if command.len() < 1 {
+        return Err("command is not given".to_string());
+    } else if command.len() < 2 {
+        return Err("Missing repository".to_string());
+    }
+
+    // validate action
+    let action = &command[0];
+    if action != "git-receive-pack" && action != "git-upload-pack" && action != "git-upload-archive" {
+        return Err("Permission denied".to_string());
+    }
+
+    // validate repository
+    let repository = &command[1];
+    if Path::new(repository).is_absolute() || repository.starts_with("./") || repository.starts_with("../") {
+        return Err("incorrect repository path".to_string());
+    }
+
+    let elems: Vec<_> = repository.split("/").collect();
+    if elems.len() != 2 {
+        return Err("Incorrect repository path".to_owned());
+    }
+
+    let user = &elems[0];
+    let project = &elems[1];
+
+    if !project.ends_with(".git") {
+        return Err("The repository URL should be end with '.git'".to_owned());
+    }
+    let project = project.trim_right_matches(".git");
+
+    Ok((action.to_owned(), (*user).to_owned(), project.to_owned()))
+}
+
+pub fn check_scope(conn: &PgConnection, action: &str, user_id: i32, project: &Project) -> Result<(), String> {
+    let access_level = project.access_level(conn, Some(user_id)).map_err(
+        |err| err.to_string(),
+    )?;
+    let required = match action {
+        "git-receive-pack" => AccessLevel::Write,
+        _ => AccessLevel::Read,
+    };
+    if access_level == AccessLevel::None {
+        return Err("The project is not created".to_owned());
+    }
+    if access_level < required {
+        return Err("Permission denied".to_string());
+    }
+    Ok(())
+}
+
+/// Resolves the command requested by the user into the git process to be run, after checking the
+/// permission to the project.
+pub fn prepare_command(conn: &PgConnection, s: &str, user_id: i32) -> Result<Command, String> {
+    let (action, user, project) = parse_ssh_command(s)?;
+
+    let project = Project::find_by_id(conn, (&user, &project))
+        .map_err(|err| err.to_string())?
+        .ok_or_else(|| "The project is not created".to_owned())?;
+    let repo = project.open_repository(conn).map_err(
+        |err| err.to_string(),
+    )?;
+
+    check_scope(conn, &action, user_id, &project)?;
+
+    let mut command = Command::new(action);
+    command
+        .arg(repo.path().to_str().unwrap())
+        .env(hooks::ENV_PROJECT_ID, project.id.to_string())
+        .env(hooks::ENV_USER_ID, user_id.to_string());
+    Ok(command)
+}
@@ -0,0 +1,296 @@
+//! Outgoing webhooks.
+//!
+//! Events are recorded as deliveries in the database by `trigger`, and sent by a background worker
+//! started by `spawn_worker`, which retries failed deliveries with exponential backoff.
+
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+use diesel::pg::PgConnection;
+use hyper::Client;
+use hyper::header::ContentType;
+use hyper::net::HttpsConnector;
+use hyper_native_tls::NativeTlsClient;
+use ring::{digest, hmac};
+use serde_json::{self, Value as JsonValue};
+
+use crypto;
+use db::DB;
+use error::AppResult;
+use models::{Project, User};
+use models::webhooks::{Webhook, WebhookDelivery, NewWebhookDelivery};
+
+
+header! {
+    (XGalliumEvent, "X-Gallium-Event") => [String]
+}
+
+header! {
+    (XGalliumDelivery, "X-Gallium-Delivery") => [String]
+}
+
+header! {
+    (XGalliumSignature, "X-Gallium-Signature") => [String]
+}
+
+
+/// The interval of polling pending deliveries, in seconds.
+const POLL_INTERVAL: u64 = 5;
+
+/// The timeout of requests to the receivers, in seconds.
+const REQUEST_TIMEOUT: u64 = 10;
+
+/// The maximum length of response bodies recorded in the delivery log.
+const MAX_RESPONSE_BODY: usize = 4096;
+
+
+/// Returns the signature of the payload, in the form of `sha256=<hex-encoded HMAC-SHA256>`.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+    let signature = hmac::sign(&key, payload.as_bytes());
+    format!("sha256={}", crypto::to_hex(signature.as_ref()))
+}
+
+/// Returns the description of a project embedded into payloads.
+pub fn project_payload(conn: &PgConnection, project: &Project) -> AppResult<JsonValue> {
+    Ok(json!({
+        "id": project.id,
+        "name": project.name,
+        "namespace": project.namespace(conn)?,
+        "visibility": project.visibility,
+    }))
+}
+
+/// Returns the description of a user embedded into payloads.
+pub fn user_payload(user: &User) -> JsonValue {
+    json!({
+        "id": user.id,
+        "name": user.name,
+    })
+}
+
+/// Enqueues the event to all webhooks of the project which subscribe to it.
+pub fn trigger(conn: &PgConnection, project_id: i32, event: &str, payload: JsonValue) -> AppResult<()> {
+    let mut payload = payload;
+    payload["event"] = json!(event);
+    let payload = serde_json::to_string(&payload)?;
+
+    for webhook in Webhook::load_by_project(conn, project_id)? {
+        if !webhook.is_subscribed(event) {
+            continue;
+        }
+        let delivery = NewWebhookDelivery {
+            webhook_id: Some(webhook.id),
+            event: event.to_owned(),
+            url: webhook.url.clone(),
+            payload: payload.clone(),
+            signature: webhook.secret.as_ref().map(|secret| sign(secret, &payload)),
+        };
+        delivery.insert(conn)?;
+    }
+    Ok(())
+}
+
+
+/// Starts the background worker which sends pending deliveries.
+pub fn spawn_worker(db: DB) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut client = match NativeTlsClient::new() {
+            Ok(tls) => Client::with_connector(HttpsConnector::new(tls)),
+            Err(_) => Client::new(),
+        };
+        client.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)));
+
+        loop {
+            if let Err(err) = deliver_pending(&db, &client) {
+                let _ = writeln!(&mut ::std::io::stderr(), "failed to deliver webhooks: {}", err);
+            }
+            thread::sleep(Duration::from_secs(POLL_INTERVAL));
+        }
+    })
+}
+
+fn deliver_pending(db: &DB, client: &Client) -> AppResult<()> {
+    let conn = db.get_db_conn()?;
+    for delivery in WebhookDelivery::load_pending(&conn)? {
+        match send(client, &delivery) {
+            Ok((status_code, body)) => delivery.mark_delivered(&conn, status_code, &body)?,
+            Err((status_code, message)) => delivery.mark_failed(&conn, status_code, &message)?,
+        }
+    }
+    Ok(())
+}
+
+fn send(client: &Client, delivery: &WebhookDelivery) -> Result<(i32, String), (Option<i32>, String)> {
+    let mut request = client
+        .post(&delivery.url)
+        .header(ContentType::json())
+        .header(XGalliumEvent(delivery.event.clone()))
+        .header(XGalliumDelivery(delivery.id.to_string()))
+        .body(delivery.payload.as_str());
+    if let Some(ref signature) = delivery.signature {
+        request = request.header(XGalliumSignature(signature.clone()));
+    }
+
+    let mut response = request.send().map_err(|err| (None, err.to_string()))?;
+    let status_code = response.status.to_u16() as i32;
+
+    let mut body = Vec::new();
+    let _ = response.by_ref().take(MAX_RESPONSE_BODY as u64).read_to_end(&mut body);
+    let body = String::from_utf8_lossy(&body).into_owned();
+
+    if response.status.is_success() {
+        Ok((status_code, body))
+    } else {
+        Err((Some(status_code), format!("The receiver responded with {}", response.status)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use chrono::NaiveDateTime;
+    use hyper::Client;
+    use models::webhooks::{WebhookDelivery, retry_delay};
+    use super::{send, sign};
+
+    /// A request received by `listen`.
+    struct Received {
+        headers: Vec<String>,
+        body: String,
+    }
+
+    impl Received {
+        fn header(&self, name: &str) -> Option<&str> {
+            let prefix = format!("{}:", name.to_lowercase());
+            self.headers
+                .iter()
+                .find(|h| h.to_lowercase().starts_with(&prefix))
+                .map(|h| h[prefix.len()..].trim())
+        }
+    }
+
+    /// Starts a receiver which answers the requests with the statuses in order, and returns its URL.
+    fn listen(statuses: Vec<u16>) -> (String, mpsc::Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_owned());
+            }
+            let mut received = Received { headers: headers, body: String::new() };
+            let len = received.header("Content-Length").unwrap().parse().unwrap();
+            reader.take(len).read_to_string(&mut received.body).unwrap();
+            tx.send(received).unwrap();
+
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {} Status\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                status
+            ).unwrap();
+        });
+        (url, rx)
+    }
+
+    fn delivery(url: &str, signature: Option<String>) -> WebhookDelivery {
+        let now = NaiveDateTime::from_timestamp(0, 0);
+        WebhookDelivery {
+            id: 42,
+            created_at: now,
+            webhook_id: Some(1),
+            event: "push".to_owned(),
+            url: url.to_owned(),
+            payload: r#"{"event":"push","ref":"refs/heads/master"}"#.to_owned(),
+            signature: signature,
+            attempts: 0,
+            next_attempt_at: now,
+            delivered_at: None,
+            status_code: None,
+            response_body: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn sign_with_hmac_sha256() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn send_payload_with_headers() {
+        let (url, rx) = listen(vec![200]);
+        let signature = sign("secret", &delivery(&url, None).payload);
+        let delivery = delivery(&url, Some(signature.clone()));
+
+        let result = send(&Client::new(), &delivery);
+        assert_eq!(result, Ok((200, "ok".to_owned())));
+
+        let received = rx.recv().unwrap();
+        assert_eq!(received.body, delivery.payload);
+        assert_eq!(received.header("Content-Type"), Some("application/json"));
+        assert_eq!(received.header("X-Gallium-Event"), Some("push"));
+        assert_eq!(received.header("X-Gallium-Delivery"), Some("42"));
+        assert_eq!(received.header("X-Gallium-Signature"), Some(signature.as_str()));
+    }
+
+    #[test]
+    fn send_without_secret() {
+        let (url, rx) = listen(vec![204]);
+        assert!(send(&Client::new(), &delivery(&url, None)).is_ok());
+        assert_eq!(rx.recv().unwrap().header("X-Gallium-Signature"), None);
+    }
+
+    #[test]
+    fn retry_after_failures() {
+        let (url, rx) = listen(vec![500, 200]);
+        let client = Client::new();
+        let delivery = delivery(&url, None);
+
+        match send(&client, &delivery) {
+            Err((Some(500), _)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(send(&client, &delivery), Ok((200, "ok".to_owned())));
+        assert_eq!(rx.recv().unwrap().body, rx.recv().unwrap().body);
+    }
+
+    #[test]
+    fn unreachable_receiver() {
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/hook", listener.local_addr().unwrap())
+        };
+        match send(&Client::new(), &delivery(&url, None)) {
+            Err((None, _)) => (),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_limit() {
+        assert_eq!(retry_delay(0), 30);
+        assert_eq!(retry_delay(1), 60);
+        assert_eq!(retry_delay(5), 960);
+        assert_eq!(retry_delay(10), 30720);
+        assert_eq!(retry_delay(100), 30720);
+    }
+}